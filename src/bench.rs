@@ -0,0 +1,169 @@
+//! Hidden `bench` subcommand comparing the engine against `std::fs::remove_dir_all`
+//!
+//! Kept in its own module so the synthetic tree generator stays out of the
+//! normal deletion path.
+
+use anyhow::{Context, Result};
+use clap::Args as ClapArgs;
+use colored::Colorize;
+use fastdel::{escape_path, DeleteOptions, DeletionEngine, DEFAULT_SIMPLE_MAX_ENTRIES};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use crate::{Concurrency, Order};
+
+/// Options for the synthetic benchmark tree
+#[derive(ClapArgs, Debug)]
+pub struct BenchArgs {
+    /// Subdirectories per directory
+    #[arg(long, value_name = "N", default_value_t = 10)]
+    #[arg(help = "Number of subdirectories in each directory")]
+    width: usize,
+
+    /// Directory levels below the root
+    #[arg(long, value_name = "N", default_value_t = 3)]
+    #[arg(help = "Number of directory levels below the root")]
+    depth: usize,
+
+    /// Files per directory
+    #[arg(long, value_name = "N", default_value_t = 20)]
+    #[arg(help = "Number of files in each directory")]
+    files: usize,
+
+    /// Size of each file
+    #[arg(long, value_name = "BYTES", default_value_t = 0)]
+    #[arg(help = "Size of each generated file in bytes")]
+    file_size: usize,
+
+    /// Maximum concurrent deletions for the engine
+    #[arg(short = 'j', long, value_name = "N")]
+    #[arg(help = "Maximum number of concurrent delete operations for fastdel [default: number of CPUs]")]
+    jobs: Option<usize>,
+
+    /// Where the engine applies concurrency
+    #[arg(long, value_enum, value_name = "MODEL", default_value_t = Concurrency::Both)]
+    #[arg(help = "Concurrency model for fastdel: files, dirs or both, as in --concurrency-model")]
+    concurrency_model: Concurrency,
+
+    /// Traversal order for the engine
+    #[arg(long, value_enum, value_name = "ORDER", default_value_t = Order::Dfs)]
+    #[arg(help = "Traversal order for fastdel: dfs or bfs, as in --delete-order")]
+    delete_order: Order,
+
+    /// Small-tree cutoff for the engine
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_SIMPLE_MAX_ENTRIES)]
+    #[arg(help = "Entry count up to which fastdel uses one remove_dir_all, as in --simple-max-entries; 0 always times the concurrent engine")]
+    simple_max_entries: u64,
+
+    /// Blocking backend for the engine
+    #[arg(long)]
+    #[arg(help = "Time fastdel's blocking std::fs backend instead of the tokio one, as in --blocking")]
+    blocking: bool,
+
+    /// Where to build the trees
+    #[arg(long, value_name = "DIR")]
+    #[arg(help = "Directory to build the benchmark trees in [default: the system temp directory]")]
+    dir: Option<PathBuf>,
+}
+
+/// Builds the same tree twice and times deleting each copy with one method
+pub async fn run(args: BenchArgs) -> Result<()> {
+    let base = args
+        .dir
+        .clone()
+        .unwrap_or_else(std::env::temp_dir)
+        .join(format!("fastdel-bench-{}", std::process::id()));
+
+    let engine_tree = base.join("engine");
+    let std_tree = base.join("std");
+    let (dirs, files) = generate(&engine_tree, &args)?;
+    generate(&std_tree, &args)?;
+    println!(
+        "Generated {} directories and {} files per tree in {}",
+        dirs,
+        files,
+        escape_path(&base)
+    );
+
+    let defaults = DeleteOptions::default();
+    let options = DeleteOptions {
+        jobs: args.jobs.unwrap_or(defaults.jobs),
+        concurrency_model: args.concurrency_model.into(),
+        delete_order: args.delete_order.into(),
+        simple_max_entries: args.simple_max_entries,
+        blocking: args.blocking,
+        ..defaults
+    };
+    let engine = DeletionEngine::new(options)?;
+    let started = Instant::now();
+    engine.delete_directory(&engine_tree).await?;
+    let engine_time = started.elapsed();
+
+    let started = Instant::now();
+    let target = std_tree.clone();
+    tokio::task::spawn_blocking(move || fs::remove_dir_all(target))
+        .await?
+        .with_context(|| format!("remove_dir_all failed: {}", escape_path(&std_tree)))?;
+    let std_time = started.elapsed();
+
+    fs::remove_dir(&base).ok();
+
+    println!();
+    print_result("fastdel", engine_time, files);
+    print_result("std::fs::remove_dir_all", std_time, files);
+    println!();
+    println!(
+        "{}",
+        format!(
+            "Speedup: {:.2}x",
+            std_time.as_secs_f64() / engine_time.as_secs_f64().max(f64::EPSILON)
+        )
+        .green()
+        .bold()
+    );
+
+    let errors = engine.get_stats().errors_encountered();
+    if errors > 0 {
+        anyhow::bail!("fastdel hit {} errors during the benchmark", errors);
+    }
+    Ok(())
+}
+
+/// Prints one timing line with its throughput
+fn print_result(label: &str, elapsed: Duration, files: u64) {
+    println!(
+        "  {:<25} {:>8.3}s  {:>10.0} files/sec",
+        label,
+        elapsed.as_secs_f64(),
+        files as f64 / elapsed.as_secs_f64().max(f64::EPSILON)
+    );
+}
+
+/// Creates the synthetic tree at `root`, returning `(dirs, files)` created
+fn generate(root: &Path, args: &BenchArgs) -> Result<(u64, u64)> {
+    fs::create_dir_all(root).with_context(|| format!("Failed to create {}", escape_path(root)))?;
+    let contents = vec![0u8; args.file_size];
+    let mut totals = (1, 0);
+    fill(root, args, args.depth, &contents, &mut totals)
+        .with_context(|| format!("Failed to generate benchmark tree in {}", escape_path(root)))?;
+    Ok(totals)
+}
+
+fn fill(dir: &Path, args: &BenchArgs, levels_left: usize, contents: &[u8], totals: &mut (u64, u64)) -> Result<()> {
+    for file in 0..args.files {
+        fs::write(dir.join(format!("file{}.dat", file)), contents)?;
+        totals.1 += 1;
+    }
+
+    if levels_left == 0 {
+        return Ok(());
+    }
+
+    for child in 0..args.width {
+        let child = dir.join(format!("dir{}", child));
+        fs::create_dir(&child)?;
+        totals.0 += 1;
+        fill(&child, args, levels_left - 1, contents, totals)?;
+    }
+    Ok(())
+}
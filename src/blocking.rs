@@ -0,0 +1,620 @@
+//! Blocking traversal backend behind `DeleteOptions::blocking`
+//!
+//! `tokio::fs` hands every call to tokio's blocking pool, which costs a
+//! thread hop per `lstat`, `unlink` and `rmdir`. Here the tree is walked by
+//! plain threads issuing `std::fs` calls themselves. Each thread keeps its
+//! own LIFO queue, so it works depth-first, and steals from the others when
+//! it runs dry. The queues belong to the engine and are shared by every
+//! target, so no more than `jobs` threads run even with several roots at
+//! once. Filters, statistics and progress reporting are the engine's own;
+//! only the scheduling differs from [`DeletionEngine::delete_tree`].
+
+use crate::{
+    escape_path, is_link, is_transient, long_path, make_removable_blocking, remove_file_or_link_blocking,
+    DeletionEngine, EntryInfo, ListedFile, ProgressEvent, SymlinkPolicy, RETRY_BASE_DELAY,
+};
+use anyhow::Result;
+use crossbeam_deque::{Injector, Steal, Stealer, Worker};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{fence, AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use tokio::runtime::{Handle, RuntimeFlavor};
+use tracing::trace;
+
+/// Files of one directory handed to another thread at a time
+///
+/// A huge flat directory is spread across the pool this way, while the
+/// files left over at the end of a listing are removed by the thread that
+/// listed them.
+const FILE_BATCH: usize = 256;
+
+/// Runs `work` on the current thread, first letting a multi-threaded runtime
+/// move its other tasks (such as the Ctrl-C handler) to another worker
+///
+/// A current-thread runtime can't do that, so its other tasks wait until
+/// `work` returns.
+pub(crate) fn run<T>(work: impl FnOnce() -> T) -> T {
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => tokio::task::block_in_place(work),
+        _ => work(),
+    }
+}
+
+/// A directory being deleted, shared by the thread listing it and the
+/// threads finishing its subdirectories
+struct Node {
+    run: Arc<Run>,
+    path: PathBuf,
+    parent: Option<Arc<Node>>,
+    /// Level below the starting directory (which is 0)
+    depth: usize,
+    /// Inside a directory matched by an `--include` pattern
+    in_included: bool,
+    /// Remove the directory itself once it is empty
+    remove: AtomicBool,
+    /// Its own listing plus every file batch and subdirectory not finished yet
+    pending: AtomicUsize,
+    /// Something inside was kept or could not be deleted
+    kept: AtomicBool,
+    /// Listed once more after "directory not empty"
+    rescanned: AtomicBool,
+}
+
+impl Node {
+    fn new(run: Arc<Run>, path: PathBuf, parent: Option<Arc<Node>>, depth: usize, in_included: bool, remove: bool) -> Self {
+        Self {
+            run,
+            path,
+            parent,
+            depth,
+            in_included,
+            remove: AtomicBool::new(remove),
+            pending: AtomicUsize::new(1),
+            kept: AtomicBool::new(false),
+            rescanned: AtomicBool::new(false),
+        }
+    }
+}
+
+/// One [`DeletionEngine::delete_tree_blocking`] call, whose tasks may be
+/// run by threads of any other call on the same engine
+struct Run {
+    /// Starting directory of the target, for the filters
+    root: PathBuf,
+    /// Tasks queued or running; the run is finished once it drops to zero
+    outstanding: AtomicUsize,
+    /// The first `fail_fast` error; later tasks are dropped unprocessed
+    failure: Mutex<Option<anyhow::Error>>,
+    failed: AtomicBool,
+    /// Whether the starting directory is still there once it is finished
+    root_kept: AtomicBool,
+}
+
+impl Run {
+    fn is_finished(&self) -> bool {
+        self.outstanding.load(Ordering::Acquire) == 0
+    }
+}
+
+/// A unit of work in the pool
+enum Task {
+    /// List a directory, removing its files and queueing its subdirectories
+    List(Arc<Node>),
+    /// Remove a batch of files found by listing the directory
+    Files(Arc<Node>, Vec<(PathBuf, u64)>),
+}
+
+impl Task {
+    fn run(&self) -> &Arc<Run> {
+        match self {
+            Task::List(node) | Task::Files(node, _) => &node.run,
+        }
+    }
+}
+
+/// Queues shared by every blocking traversal of one engine
+///
+/// There is one queue per job. A thread takes a free queue before it starts
+/// and hands it back when it leaves, which caps the threads at `jobs` across
+/// all targets; tasks left in a returned queue are stolen by the others.
+pub(crate) struct Pool {
+    injector: Injector<Task>,
+    stealers: Vec<Stealer<Task>>,
+    /// Queues not taken by a thread
+    free: Mutex<Vec<Worker<Task>>>,
+    /// Signalled when a task is queued or a run finishes
+    work_ready: Condvar,
+    /// Signalled when a queue is handed back or a run finishes
+    released: Condvar,
+    /// Threads waiting on `work_ready`
+    sleepers: AtomicUsize,
+}
+
+impl Pool {
+    pub(crate) fn new(jobs: usize) -> Self {
+        let workers: Vec<Worker<Task>> = (0..jobs).map(|_| Worker::new_lifo()).collect();
+        Self {
+            injector: Injector::new(),
+            stealers: workers.iter().map(Worker::stealer).collect(),
+            free: Mutex::new(workers),
+            work_ready: Condvar::new(),
+            released: Condvar::new(),
+            sleepers: AtomicUsize::new(0),
+        }
+    }
+
+    fn push(&self, local: &Worker<Task>, task: Task) {
+        task.run().outstanding.fetch_add(1, Ordering::AcqRel);
+        local.push(task);
+        self.wake_one();
+    }
+
+    /// Wakes one sleeping thread, if any, to pick up a newly queued task
+    fn wake_one(&self) {
+        // Pairs with the fence in `park`: either the sleeper sees the task,
+        // or the sleeper is seen here
+        fence(Ordering::SeqCst);
+        if self.sleepers.load(Ordering::SeqCst) > 0 {
+            let _free = self.free.lock().unwrap();
+            self.work_ready.notify_one();
+        }
+    }
+
+    /// Pops local work, or steals a batch from the injector or one task from
+    /// another thread
+    fn next(&self, local: &Worker<Task>) -> Option<Task> {
+        local.pop().or_else(|| {
+            std::iter::repeat_with(|| {
+                self.injector
+                    .steal_batch_and_pop(local)
+                    .or_else(|| self.stealers.iter().map(Stealer::steal).collect())
+            })
+            .find(|steal| !steal.is_retry())
+            .and_then(Steal::success)
+        })
+    }
+
+    fn has_work(&self) -> bool {
+        !self.injector.is_empty() || self.stealers.iter().any(|stealer| !stealer.is_empty())
+    }
+
+    /// Sleeps until a task is queued or `run` finishes
+    fn park(&self, run: &Run) {
+        let free = self.free.lock().unwrap();
+        self.sleepers.fetch_add(1, Ordering::SeqCst);
+        fence(Ordering::SeqCst);
+        let free = if self.has_work() || run.is_finished() {
+            free
+        } else {
+            self.work_ready.wait(free).unwrap()
+        };
+        self.sleepers.fetch_sub(1, Ordering::SeqCst);
+        drop(free);
+    }
+
+    /// Counts a task of `run` as done, waking every thread waiting on the
+    /// run after its last one
+    fn complete(&self, run: &Run) {
+        if run.outstanding.fetch_sub(1, Ordering::AcqRel) == 1 {
+            let _free = self.free.lock().unwrap();
+            self.work_ready.notify_all();
+            self.released.notify_all();
+        }
+    }
+
+    /// Hands a thread's queue back for the next thread to take
+    fn release(&self, local: Worker<Task>) {
+        let left_over = !local.is_empty();
+        self.free.lock().unwrap().push(local);
+        if left_over {
+            self.work_ready.notify_all();
+        }
+        self.released.notify_all();
+    }
+}
+
+impl DeletionEngine {
+    /// Blocking counterpart of [`DeletionEngine::delete_tree`], with the same
+    /// arguments and result
+    ///
+    /// The calling thread only waits: it starts a thread for every queue of
+    /// `pool` that is free, including ones handed back while the run goes on,
+    /// and returns once every task of the run is done.
+    pub(crate) fn delete_tree_blocking(&self, pool: &Pool, root: &Path, dir_path: &Path, remove_root: bool) -> Result<bool> {
+        let run = Arc::new(Run {
+            root: root.to_path_buf(),
+            outstanding: AtomicUsize::new(1),
+            failure: Mutex::new(None),
+            failed: AtomicBool::new(false),
+            root_kept: AtomicBool::new(false),
+        });
+        let node = Node::new(Arc::clone(&run), dir_path.to_path_buf(), None, 0, false, remove_root);
+        pool.injector.push(Task::List(Arc::new(node)));
+        pool.wake_one();
+
+        // Spans and a scoped subscriber are per thread, so carry them over
+        let span = tracing::Span::current();
+        let dispatch = tracing::dispatcher::get_default(|dispatch| dispatch.clone());
+        std::thread::scope(|scope| {
+            let mut free = pool.free.lock().unwrap();
+            while !run.is_finished() {
+                let Some(local) = free.pop() else {
+                    free = pool.released.wait(free).unwrap();
+                    continue;
+                };
+                let (run, span, dispatch) = (&run, &span, &dispatch);
+                scope.spawn(move || {
+                    tracing::dispatcher::with_default(dispatch, || span.in_scope(|| self.work(pool, run, local)))
+                });
+            }
+        });
+
+        let failure = run.failure.lock().unwrap().take();
+        match failure {
+            Some(error) => Err(error),
+            None => Ok(run.root_kept.load(Ordering::Relaxed)),
+        }
+    }
+
+    /// Runs tasks of any target on one thread until `run` is finished, then
+    /// hands its queue back
+    fn work(&self, pool: &Pool, run: &Run, local: Worker<Task>) {
+        while !run.is_finished() {
+            let Some(task) = pool.next(&local) else {
+                pool.park(run);
+                continue;
+            };
+
+            let task_run = Arc::clone(task.run());
+            if !task_run.failed.load(Ordering::Relaxed) {
+                let result = match task {
+                    Task::List(node) => self.list_blocking(pool, &local, node),
+                    Task::Files(node, files) => self.remove_batch_blocking(pool, &local, node, files),
+                };
+                if let Err(e) = result {
+                    task_run.failed.store(true, Ordering::Relaxed);
+                    task_run.failure.lock().unwrap().get_or_insert(e);
+                }
+            }
+            pool.complete(&task_run);
+        }
+        pool.release(local);
+    }
+
+    /// Lists one directory, handing its files out in batches and queueing
+    /// its subdirectories
+    fn list_blocking(&self, pool: &Pool, local: &Worker<Task>, node: Arc<Node>) -> Result<()> {
+        // After cancellation everything not yet removed counts as kept
+        if self.is_cancelled() {
+            node.kept.store(true, Ordering::Relaxed);
+            return self.complete_blocking(pool, local, node);
+        }
+
+        let root = node.run.root.as_path();
+        let filter_included = !self.includes.is_empty() && !node.in_included;
+        self.stats.increment_fs_calls();
+        let entries = match std::fs::read_dir(long_path(&node.path)) {
+            Ok(entries) => entries,
+            // Without filters there is nothing to check the file against first
+            Err(e) if e.kind() == io::ErrorKind::NotADirectory && node.depth > 0 && !self.has_filters() => {
+                let removed = self.remove_replaced_directory_blocking(&node.path)?;
+                node.kept.fetch_or(!removed, Ordering::Relaxed);
+                node.remove.store(false, Ordering::Relaxed);
+                return self.complete_blocking(pool, local, node);
+            }
+            Err(e) => {
+                node.kept.store(true, Ordering::Relaxed);
+                let message = self.report_error("Failed to read directory", &node.path, &e);
+                self.check_fail_fast(&message)?;
+                return self.complete_blocking(pool, local, node);
+            }
+        };
+
+        let mut subdirs = Vec::new();
+        let mut batch = Vec::new();
+        let (mut kept, mut found_files, mut listed) = (false, false, false);
+        for entry in entries {
+            let Ok(entry) = entry else {
+                break;
+            };
+            listed = true;
+            let path = node.path.join(entry.file_name());
+            if self.is_excluded(root, &path) {
+                kept = true;
+                self.stats.increment_skipped();
+                trace!("Skipping excluded entry: {}", escape_path(&path));
+                continue;
+            }
+
+            let info = if self.assume_empty_fast_delete {
+                entry.file_type().map(EntryInfo::Type)
+            } else {
+                self.entry_metadata_blocking(&path).map(EntryInfo::Metadata)
+            };
+            let size = match info {
+                Ok(EntryInfo::Type(file_type)) if file_type.is_dir() => {
+                    subdirs.push((path, false));
+                    continue;
+                }
+                Ok(EntryInfo::Type(_)) => 0,
+                Ok(EntryInfo::Metadata(metadata)) if self.on_symlink == SymlinkPolicy::Skip && is_link(&metadata) => {
+                    kept = true;
+                    self.stats.increment_symlinks_skipped();
+                    trace!("Skipping symlink: {}", escape_path(&path));
+                    continue;
+                }
+                Ok(EntryInfo::Metadata(metadata)) if metadata.is_dir() && !is_link(&metadata) => {
+                    let included = filter_included && self.is_included(root, &path);
+                    subdirs.push((path, included));
+                    continue;
+                }
+                Ok(EntryInfo::Metadata(metadata)) => {
+                    if self.keeps_listed_file(root, &path, &metadata, filter_included, || false) {
+                        kept = true;
+                        continue;
+                    }
+                    self.freed_size(&metadata)
+                }
+                Err(e) => {
+                    kept = true;
+                    let message = self.report_error("Failed to get metadata for", &path, &e);
+                    self.check_fail_fast(&message)?;
+                    continue;
+                }
+            };
+
+            found_files = true;
+            batch.push((path, size));
+            if batch.len() == FILE_BATCH {
+                node.pending.fetch_add(1, Ordering::AcqRel);
+                pool.push(local, Task::Files(Arc::clone(&node), std::mem::take(&mut batch)));
+            }
+        }
+        if kept {
+            node.kept.store(true, Ordering::Relaxed);
+        }
+        if self.report_empty && !listed && !node.rescanned.load(Ordering::Relaxed) {
+            self.stats.record_empty_dir(&node.path);
+        }
+
+        subdirs.extend(self.remove_files_blocking(&node, batch)?);
+
+        // With --include, only directories emptied by this run are removed
+        if filter_included && !node.kept.load(Ordering::Relaxed) && !found_files && subdirs.is_empty() {
+            trace!("Keeping empty directory not matched by --include: {}", escape_path(&node.path));
+            node.kept.store(true, Ordering::Relaxed);
+        }
+
+        self.queue_subdirs_blocking(pool, local, &node, subdirs);
+        self.complete_blocking(pool, local, node)
+    }
+
+    /// Runs a [`Task::Files`] batch
+    fn remove_batch_blocking(
+        &self,
+        pool: &Pool,
+        local: &Worker<Task>,
+        node: Arc<Node>,
+        files: Vec<(PathBuf, u64)>,
+    ) -> Result<()> {
+        let replaced = self.remove_files_blocking(&node, files)?;
+        self.queue_subdirs_blocking(pool, local, &node, replaced);
+        self.complete_blocking(pool, local, node)
+    }
+
+    /// Removes files of `node`, returning any that were replaced by a
+    /// directory since they were listed, ready to queue as subdirectories
+    fn remove_files_blocking(&self, node: &Node, files: Vec<(PathBuf, u64)>) -> Result<Vec<(PathBuf, bool)>> {
+        // The file passed every filter, so the directory in its place is
+        // descended into as if it had matched them as a whole
+        let filter_included = !self.includes.is_empty() && !node.in_included;
+        let mut replaced = Vec::new();
+        for (path, size) in files {
+            match self.remove_listed_file_blocking(&path, size)? {
+                ListedFile::Done(true) => {}
+                ListedFile::Done(false) => node.kept.store(true, Ordering::Relaxed),
+                ListedFile::Replaced(_) => replaced.push((path, filter_included)),
+            }
+        }
+        Ok(replaced)
+    }
+
+    /// Queues subdirectories of `node`, or skips them past `max_depth`
+    fn queue_subdirs_blocking(&self, pool: &Pool, local: &Worker<Task>, node: &Arc<Node>, subdirs: Vec<(PathBuf, bool)>) {
+        if subdirs.is_empty() {
+            return;
+        }
+        if self.max_depth.is_some_and(|max_depth| node.depth >= max_depth) {
+            for (path, _) in &subdirs {
+                self.stats.increment_skipped();
+                trace!("Skipping directory beyond max depth: {}", escape_path(path));
+            }
+            node.kept.store(true, Ordering::Relaxed);
+            return;
+        }
+
+        node.pending.fetch_add(subdirs.len(), Ordering::AcqRel);
+        for (path, included) in subdirs {
+            let child = Node::new(
+                Arc::clone(&node.run),
+                path,
+                Some(Arc::clone(node)),
+                node.depth + 1,
+                node.in_included || included,
+                true,
+            );
+            pool.push(local, Task::List(Arc::new(child)));
+        }
+    }
+
+    /// Marks one piece of a directory's work as done, finishing the
+    /// directory (and every parent this leaves finished) after the last
+    fn complete_blocking(&self, pool: &Pool, local: &Worker<Task>, mut node: Arc<Node>) -> Result<()> {
+        loop {
+            if node.pending.fetch_sub(1, Ordering::AcqRel) != 1 {
+                return Ok(());
+            }
+
+            // After cancellation everything not yet removed counts as kept
+            let kept = node.kept.load(Ordering::Relaxed) || self.is_cancelled();
+            let still_there = if kept || !node.remove.load(Ordering::Relaxed) {
+                kept
+            } else if self.dry_run {
+                self.stats.increment_dirs();
+                let message = format!("Would delete directory: {}", escape_path(&node.path));
+                self.report_progress(&ProgressEvent::directory(&node.path), &message);
+                false
+            } else {
+                match self.retry_blocking(&node.path, || std::fs::remove_dir(long_path(&node.path))) {
+                    Ok(()) => {
+                        self.stats.increment_dirs();
+                        let message = format!("Deleted directory: {}", escape_path(&node.path));
+                        self.report_progress(&ProgressEvent::directory(&node.path), &message);
+                        false
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::DirectoryNotEmpty && !node.rescanned.load(Ordering::Relaxed) => {
+                        trace!("Directory not empty, rescanning: {}", escape_path(&node.path));
+                        node.rescanned.store(true, Ordering::Relaxed);
+                        node.pending.store(1, Ordering::Relaxed);
+                        pool.push(local, Task::List(node));
+                        return Ok(());
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::NotADirectory && !self.has_filters() => {
+                        !self.remove_replaced_directory_blocking(&node.path)?
+                    }
+                    Err(e) => {
+                        let message = self.report_error("Failed to delete directory", &node.path, &e);
+                        self.check_fail_fast(&message)?;
+                        true
+                    }
+                }
+            };
+
+            let Some(ref parent) = node.parent else {
+                node.run.root_kept.store(still_there, Ordering::Relaxed);
+                return Ok(());
+            };
+            parent.kept.fetch_or(still_there, Ordering::Relaxed);
+            node = Arc::clone(parent);
+        }
+    }
+
+    /// Blocking counterpart of [`DeletionEngine::remove_listed_file`]
+    fn remove_listed_file_blocking(&self, path: &Path, size: u64) -> Result<ListedFile> {
+        if self.dry_run {
+            return Ok(ListedFile::Done(self.remove_file_blocking(path, size)?));
+        }
+
+        match self.attempt_file_removal_blocking(path, size) {
+            Ok(removed) => Ok(ListedFile::Done(removed)),
+            Err(e) => match self.entry_metadata_blocking(path) {
+                Ok(metadata) if metadata.is_dir() && !is_link(&metadata) => {
+                    trace!("File was replaced by a directory, deleting it as one: {}", escape_path(path));
+                    Ok(ListedFile::Replaced(metadata))
+                }
+                _ => {
+                    let message = self.report_error("Failed to delete file", path, &e);
+                    self.check_fail_fast(&message)?;
+                    Ok(ListedFile::Done(false))
+                }
+            },
+        }
+    }
+
+    /// Blocking counterpart of [`DeletionEngine::remove_replaced_directory`]
+    fn remove_replaced_directory_blocking(&self, path: &Path) -> Result<bool> {
+        trace!("Directory was replaced by a file, deleting it as one: {}", escape_path(path));
+        match self.entry_metadata_blocking(path) {
+            Ok(metadata) => self.remove_file_blocking(path, self.freed_size(&metadata)),
+            Err(e) => {
+                let message = self.report_error("Failed to get metadata for", path, &e);
+                self.check_fail_fast(&message)?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Blocking counterpart of [`DeletionEngine::remove_file`]
+    fn remove_file_blocking(&self, path: &Path, size: u64) -> Result<bool> {
+        if self.dry_run {
+            self.stats.increment_files();
+            self.stats.add_bytes(size);
+            self.check_min_free();
+            let message = format!("Would delete file: {}", escape_path(path));
+            self.report_progress(&ProgressEvent::file(path, size), &message);
+            return Ok(true);
+        }
+
+        match self.attempt_file_removal_blocking(path, size) {
+            Ok(removed) => Ok(removed),
+            Err(e) => {
+                let message = self.report_error("Failed to delete file", path, &e);
+                self.check_fail_fast(&message)?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Removes a file with retries and records it if it succeeded
+    ///
+    /// `Ok(false)` means the run was cancelled before the file was attempted.
+    fn attempt_file_removal_blocking(&self, path: &Path, size: u64) -> io::Result<bool> {
+        if self.is_cancelled() {
+            return Ok(false);
+        }
+        self.retry_blocking(path, || remove_file_or_link_blocking(path))?;
+        self.stats.increment_files();
+        self.stats.add_bytes(size);
+        self.check_min_free();
+        let message = format!("Deleted file: {}", escape_path(path));
+        self.report_progress(&ProgressEvent::file(path, size), &message);
+        Ok(true)
+    }
+
+    /// Blocking counterpart of [`DeletionEngine::entry_metadata`]
+    fn entry_metadata_blocking(&self, path: &Path) -> io::Result<std::fs::Metadata> {
+        self.stats.increment_fs_calls();
+        let path = long_path(path);
+        if self.on_symlink == SymlinkPolicy::Follow {
+            std::fs::metadata(&path)
+        } else {
+            std::fs::symlink_metadata(&path)
+        }
+    }
+
+    /// Blocking counterpart of [`DeletionEngine::with_retries`]
+    fn retry_blocking(&self, path: &Path, operation: impl Fn() -> io::Result<()>) -> io::Result<()> {
+        let mut attempt = 0;
+        loop {
+            let in_flight = self.stats.begin_removal();
+            let result = operation();
+            drop(in_flight);
+            match result {
+                Err(e) if attempt < self.retries && is_transient(&e) => {
+                    let delay = RETRY_BASE_DELAY * 2u32.saturating_pow(attempt);
+                    attempt += 1;
+                    trace!(
+                        "Retrying {} in {}ms (attempt {}/{}): {}",
+                        escape_path(path),
+                        delay.as_millis(),
+                        attempt,
+                        self.retries,
+                        e
+                    );
+                    std::thread::sleep(delay);
+                }
+                Err(e) if e.kind() == io::ErrorKind::PermissionDenied && self.may_force(path) => {
+                    trace!("Clearing read-only attribute on {}", escape_path(path));
+                    if let Err(clear_error) = make_removable_blocking(path) {
+                        trace!("Failed to clear read-only attribute on {}: {}", escape_path(path), clear_error);
+                        return Err(e);
+                    }
+                    let _in_flight = self.stats.begin_removal();
+                    return operation();
+                }
+                result => return result,
+            }
+        }
+    }
+}
@@ -0,0 +1,143 @@
+//! Default option values loaded from a `.fastdel.toml` config file
+//!
+//! Only options that make sense as a standing default are accepted; per-run
+//! choices like `--dry-run` or the age and size filters must be given on the
+//! command line. Keys use the long flag names, e.g. `jobs = 8` or
+//! `exclude = [".git"]`.
+
+use crate::{parse_confirm_threshold, Args, ColorChoice};
+use anyhow::{Context, Result};
+use clap::parser::ValueSource;
+use clap::ArgMatches;
+use fastdel::escape_path;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// Name of the config file looked up in the current directory
+const LOCAL_CONFIG: &str = ".fastdel.toml";
+
+/// Contents of a config file; every key is optional
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    yes: bool,
+    default_yes: bool,
+    verbose: bool,
+    quiet: bool,
+    color: Option<ColorChoice>,
+    jobs: Option<usize>,
+    max_open_dirs: Option<usize>,
+    simple_max_entries: Option<u64>,
+    trash: bool,
+    trash_on_error: bool,
+    exclude: Option<Vec<String>>,
+    include: Option<Vec<String>>,
+    one_file_system: bool,
+    progress: bool,
+    retries: Option<u32>,
+    force: bool,
+    confirm_depth: Option<usize>,
+    count_before: bool,
+    no_confirm_under: Option<String>,
+    list_errors: bool,
+    log_file: Option<PathBuf>,
+    verify: bool,
+    protected: Option<Vec<PathBuf>>,
+}
+
+impl Config {
+    /// Loads the first config file found, if any
+    ///
+    /// `./.fastdel.toml` is used if present, otherwise
+    /// `$XDG_CONFIG_HOME/fastdel/config.toml` (`~/.config` when the variable
+    /// is unset). The files are never merged with each other.
+    pub fn load() -> Result<Option<(PathBuf, Self)>> {
+        let Some(path) = config_paths().into_iter().find(|path| path.is_file()) else {
+            return Ok(None);
+        };
+        let config = Self::read(&path)?;
+        Ok(Some((path, config)))
+    }
+
+    fn read(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file: {}", escape_path(path)))?;
+        toml::from_str(&text).with_context(|| format!("Invalid config file: {}", escape_path(path)))
+    }
+
+    /// Fills in every option that wasn't given on the command line
+    ///
+    /// A flag on the command line always wins. List options such as `exclude`
+    /// are replaced, not extended, when given on the command line. Switches
+    /// that clap would reject together (like `quiet` and `--verbose`) are only
+    /// taken from the file when the command line doesn't contradict them.
+    pub fn apply(self, args: &mut Args, matches: &ArgMatches) -> Result<()> {
+        let from_cli = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+
+        args.yes |= self.yes;
+        args.default_yes |= self.default_yes;
+        if self.verbose && !args.quiet {
+            args.verbose = args.verbose.max(1);
+        }
+        args.quiet |= self.quiet && !(args.verbose > 0 || args.progress || args.json || args.progress_stream);
+        args.progress |= self.progress && !args.quiet;
+        args.trash |= self.trash && !(args.dry_run || args.total_only || args.trash_on_error);
+        args.trash_on_error |= self.trash_on_error && !(args.trash || args.dry_run || args.total_only);
+        args.one_file_system |= self.one_file_system;
+        args.force |= self.force;
+        args.list_errors |= self.list_errors;
+        args.verify |= self.verify;
+        args.count_before |= self.count_before;
+
+        if args.jobs.is_none() {
+            args.jobs = self.jobs;
+        }
+        if args.log_file.is_none() {
+            args.log_file = self.log_file;
+        }
+        if let (Some(color), false) = (self.color, from_cli("color")) {
+            args.color = color;
+        }
+        if let (Some(max_open_dirs), false) = (self.max_open_dirs, from_cli("max_open_dirs")) {
+            args.max_open_dirs = max_open_dirs;
+        }
+        if let (Some(simple_max_entries), false) = (self.simple_max_entries, from_cli("simple_max_entries") || args.simple) {
+            args.simple_max_entries = simple_max_entries;
+        }
+        if let (Some(retries), false) = (self.retries, from_cli("retries")) {
+            args.retries = retries;
+        }
+        if let (Some(confirm_depth), false) = (self.confirm_depth, from_cli("confirm_depth")) {
+            args.confirm_depth = confirm_depth;
+        }
+        if let (Some(exclude), false) = (self.exclude, from_cli("exclude")) {
+            args.exclude = exclude;
+        }
+        if let (Some(include), false) = (self.include, from_cli("include")) {
+            args.include = include;
+        }
+        if self.protected.is_some() {
+            args.protected = self.protected;
+        }
+        if let (Some(threshold), None) = (self.no_confirm_under, args.no_confirm_under) {
+            args.no_confirm_under = Some(
+                parse_confirm_threshold(&threshold)
+                    .map_err(|e| anyhow::anyhow!("Invalid no-confirm-under in config file: {}", e))?,
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Candidate config files, in order of precedence
+fn config_paths() -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::from(LOCAL_CONFIG)];
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| crate::home_dir().map(|home| home.join(".config")));
+    if let Some(config_home) = config_home {
+        paths.push(config_home.join("fastdel").join("config.toml"));
+    }
+    paths
+}
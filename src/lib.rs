@@ -0,0 +1,4104 @@
+//! FastDel - A high-performance directory deletion library
+//!
+//! This crate contains the deletion engine behind the `fastdel` CLI so it can be
+//! embedded by build tools without shelling out. The simplest entry point is
+//! [`delete`], which takes a [`DeleteOptions`] and returns the final
+//! [`DeletionStats`]; [`DeletionEngine`] can be used directly to run several
+//! targets against one set of statistics.
+//!
+//! Diagnostics are reported through [`tracing`]: each target runs in a
+//! `delete` span, skipped and kept entries are `DEBUG` events, failures are
+//! `WARN` events and every completed removal is a `TRACE` event.
+
+use anyhow::{Context, Result};
+use futures::channel::mpsc;
+use futures::SinkExt;
+use futures::stream::{self, FuturesUnordered, StreamExt};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use regex::Regex;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use std::future::Future;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::fs;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::Semaphore;
+use tracing::instrument::WithSubscriber;
+use tracing::subscriber::NoSubscriber;
+use tracing::{debug, info, trace, warn, Instrument};
+
+mod blocking;
+
+/// Statistics tracking for the deletion operation
+#[derive(Debug, Default)]
+pub struct DeletionStats {
+    files_deleted: AtomicU64,
+    dirs_deleted: AtomicU64,
+    errors_encountered: AtomicU64,
+    bytes_freed: AtomicU64,
+    skipped: AtomicU64,
+    mounts_skipped: AtomicU64,
+    symlinks_skipped: AtomicU64,
+    permission_errors: AtomicU64,
+    not_found_errors: AtomicU64,
+    in_use_errors: AtomicU64,
+    verification_failures: AtomicU64,
+    survivors: Mutex<Vec<PathBuf>>,
+    trash_fallbacks: AtomicU64,
+    backup_renames: AtomicU64,
+    backup_copies: AtomicU64,
+    placeholders_created: AtomicU64,
+    in_flight: AtomicU64,
+    peak_in_flight: AtomicU64,
+    fs_calls: AtomicU64,
+    empty_dirs: Mutex<Vec<PathBuf>>,
+    special_files: Mutex<Vec<PathBuf>>,
+    special_kept: Mutex<Vec<PathBuf>>,
+    kept_roots: Mutex<Vec<(PathBuf, &'static str)>>,
+    breakdown: Mutex<Vec<DirectoryBreakdown>>,
+    failures: Mutex<Vec<(PathBuf, io::Error)>>,
+}
+
+/// How symlinks found inside a target are treated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SymlinkPolicy {
+    /// Leave the link in place, which also keeps its parent directories
+    Skip,
+    /// Remove the link itself, never what it points to
+    #[default]
+    Unlink,
+    /// Descend into linked directories and delete what they point to
+    ///
+    /// This can delete files outside the target tree.
+    Follow,
+}
+
+/// Which levels of the traversal run concurrently, up to `jobs` at a time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConcurrencyModel {
+    /// Unlink the files of a directory concurrently, but descend into one
+    /// subdirectory at a time
+    Files,
+    /// Descend into sibling subdirectories concurrently, but unlink the files
+    /// of each directory one at a time
+    Dirs,
+    /// Both of the above
+    #[default]
+    Both,
+}
+
+/// Order in which the work queue hands out directories to list
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DeleteOrder {
+    /// Depth-first: finish the most recently found subtree before moving on,
+    /// so only one branch of the tree is pending at a time
+    #[default]
+    Dfs,
+    /// Breadth-first: list every directory of one level before the next, so
+    /// shallow files go first, at the cost of keeping a whole level queued
+    Bfs,
+}
+
+/// Broad cause of a failed read or removal, used to suggest a fix
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCategory {
+    /// Access was denied; `--force` or elevated privileges may help
+    PermissionDenied,
+    /// The entry disappeared while the run was in progress
+    NotFound,
+    /// Another process holds the file open or locked
+    InUse,
+    Other,
+}
+
+impl ErrorCategory {
+    /// Classifies an I/O error by its kind and, on Windows, its raw code
+    pub fn of(error: &io::Error) -> Self {
+        #[cfg(windows)]
+        if matches!(error.raw_os_error(), Some(ERROR_SHARING_VIOLATION | ERROR_LOCK_VIOLATION)) {
+            return ErrorCategory::InUse;
+        }
+
+        match error.kind() {
+            io::ErrorKind::PermissionDenied => ErrorCategory::PermissionDenied,
+            io::ErrorKind::NotFound => ErrorCategory::NotFound,
+            io::ErrorKind::ResourceBusy | io::ErrorKind::ExecutableFileBusy => ErrorCategory::InUse,
+            _ => ErrorCategory::Other,
+        }
+    }
+
+    /// Short human-readable name for summaries
+    pub fn label(self) -> &'static str {
+        match self {
+            ErrorCategory::PermissionDenied => "Permission denied",
+            ErrorCategory::NotFound => "Not found",
+            ErrorCategory::InUse => "In use / locked",
+            ErrorCategory::Other => "Other",
+        }
+    }
+}
+
+/// Copies an I/O error, keeping its OS error code when it has one
+fn clone_io_error(error: &io::Error) -> io::Error {
+    match error.raw_os_error() {
+        Some(code) => io::Error::from_raw_os_error(code),
+        None => io::Error::new(error.kind(), error.to_string()),
+    }
+}
+
+/// Totals for a single top-level subdirectory of a target
+#[derive(Debug, Clone)]
+pub struct DirectoryBreakdown {
+    /// The immediate child of the target root this entry covers
+    pub path: PathBuf,
+    pub files: u64,
+    pub bytes: u64,
+    /// Wall-clock time spent deleting the subtree
+    pub elapsed: Duration,
+}
+
+/// Marks one removal as in flight for [`DeletionStats::peak_concurrency`]
+struct InFlight<'a>(&'a DeletionStats);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Running counters for one top-level subtree, shared by the directories below it
+#[derive(Debug, Default)]
+struct SubtreeTally {
+    files: AtomicU64,
+    bytes: AtomicU64,
+}
+
+impl SubtreeTally {
+    fn add_file(&self, bytes: u64) {
+        self.files.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+}
+
+impl DeletionStats {
+    fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    fn increment_files(&self) {
+        self.files_deleted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn increment_dirs(&self) {
+        self.dirs_deleted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn increment_errors(&self, category: ErrorCategory) {
+        self.errors_encountered.fetch_add(1, Ordering::Relaxed);
+        match category {
+            ErrorCategory::PermissionDenied => self.permission_errors.fetch_add(1, Ordering::Relaxed),
+            ErrorCategory::NotFound => self.not_found_errors.fetch_add(1, Ordering::Relaxed),
+            ErrorCategory::InUse => self.in_use_errors.fetch_add(1, Ordering::Relaxed),
+            ErrorCategory::Other => 0,
+        };
+    }
+
+    fn record_failure(&self, path: &Path, error: &io::Error) {
+        if let Ok(mut failures) = self.failures.lock() {
+            failures.push((path.to_path_buf(), clone_io_error(error)));
+        }
+    }
+
+    fn add_bytes(&self, bytes: u64) {
+        self.bytes_freed.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    fn increment_skipped(&self) {
+        self.skipped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn increment_mounts_skipped(&self) {
+        self.mounts_skipped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn increment_symlinks_skipped(&self) {
+        self.symlinks_skipped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn increment_trash_fallbacks(&self) {
+        self.trash_fallbacks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn increment_backup_renames(&self) {
+        self.backup_renames.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn increment_backup_copies(&self) {
+        self.backup_copies.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn increment_placeholders(&self) {
+        self.placeholders_created.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a removal attempt as in flight until the returned guard drops
+    fn begin_removal(&self) -> InFlight<'_> {
+        self.fs_calls.fetch_add(1, Ordering::Relaxed);
+        let in_flight = self.in_flight.fetch_add(1, Ordering::Relaxed) + 1;
+        self.peak_in_flight.fetch_max(in_flight, Ordering::Relaxed);
+        InFlight(self)
+    }
+
+    fn increment_fs_calls(&self) {
+        self.fs_calls.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record_survivor(&self, path: &Path) {
+        self.verification_failures.fetch_add(1, Ordering::Relaxed);
+        if let Ok(mut survivors) = self.survivors.lock() {
+            survivors.push(path.to_path_buf());
+        }
+    }
+
+    fn record_empty_dir(&self, path: &Path) {
+        if let Ok(mut empty_dirs) = self.empty_dirs.lock() {
+            empty_dirs.push(path.to_path_buf());
+        }
+    }
+
+    fn record_special_file(&self, path: &Path) {
+        if let Ok(mut special_files) = self.special_files.lock() {
+            special_files.push(path.to_path_buf());
+        }
+    }
+
+    fn record_special_kept(&self, path: &Path) {
+        if let Ok(mut special_kept) = self.special_kept.lock() {
+            special_kept.push(path.to_path_buf());
+        }
+    }
+
+    fn record_kept_root(&self, path: &Path, reason: &'static str) {
+        if let Ok(mut kept_roots) = self.kept_roots.lock() {
+            kept_roots.push((path.to_path_buf(), reason));
+        }
+    }
+
+    fn record_breakdown(&self, entry: DirectoryBreakdown) {
+        if let Ok(mut breakdown) = self.breakdown.lock() {
+            breakdown.push(entry);
+        }
+    }
+
+    fn add_scan(&self, totals: ScanTotals) {
+        self.files_deleted.fetch_add(totals.files, Ordering::Relaxed);
+        self.dirs_deleted.fetch_add(totals.dirs, Ordering::Relaxed);
+        self.bytes_freed.fetch_add(totals.bytes, Ordering::Relaxed);
+    }
+
+    /// Adds every counter and list of `other` into these statistics
+    ///
+    /// Use this to accumulate the results of several runs, e.g. one
+    /// [`delete`] call per target, into a cumulative total. Each counter is
+    /// loaded and added on its own, so merging while `other` is still being
+    /// updated gives a consistent per-counter, not per-run, snapshot.
+    /// Merging statistics into themselves doubles them.
+    pub fn merge_from(&self, other: &DeletionStats) {
+        let counters = [
+            (&self.files_deleted, &other.files_deleted),
+            (&self.dirs_deleted, &other.dirs_deleted),
+            (&self.errors_encountered, &other.errors_encountered),
+            (&self.bytes_freed, &other.bytes_freed),
+            (&self.skipped, &other.skipped),
+            (&self.mounts_skipped, &other.mounts_skipped),
+            (&self.symlinks_skipped, &other.symlinks_skipped),
+            (&self.permission_errors, &other.permission_errors),
+            (&self.not_found_errors, &other.not_found_errors),
+            (&self.in_use_errors, &other.in_use_errors),
+            (&self.verification_failures, &other.verification_failures),
+            (&self.trash_fallbacks, &other.trash_fallbacks),
+            (&self.backup_renames, &other.backup_renames),
+            (&self.backup_copies, &other.backup_copies),
+            (&self.placeholders_created, &other.placeholders_created),
+            (&self.fs_calls, &other.fs_calls),
+        ];
+        for (total, counter) in counters {
+            total.fetch_add(counter.load(Ordering::Relaxed), Ordering::Relaxed);
+        }
+        self.peak_in_flight
+            .fetch_max(other.peak_in_flight.load(Ordering::Relaxed), Ordering::Relaxed);
+
+        // Snapshot `other` before locking `self`, which may be the same object
+        let survivors = other.survivors();
+        let breakdown = other.breakdown();
+        let empty_dirs = other.empty_dirs();
+        let special_files = other.special_files();
+        let special_kept = other.special_kept();
+        let kept_roots = other.kept_roots();
+        let failures = other.failures();
+        if let Ok(mut list) = self.survivors.lock() {
+            list.extend(survivors);
+        }
+        if let Ok(mut list) = self.breakdown.lock() {
+            list.extend(breakdown);
+        }
+        if let Ok(mut list) = self.empty_dirs.lock() {
+            list.extend(empty_dirs);
+        }
+        if let Ok(mut list) = self.special_files.lock() {
+            list.extend(special_files);
+        }
+        if let Ok(mut list) = self.special_kept.lock() {
+            list.extend(special_kept);
+        }
+        if let Ok(mut list) = self.kept_roots.lock() {
+            list.extend(kept_roots);
+        }
+        if let Ok(mut list) = self.failures.lock() {
+            list.extend(failures);
+        }
+    }
+
+    /// Returns `(files, dirs, errors, bytes, skipped)` in a single snapshot
+    pub fn get_summary(&self) -> (u64, u64, u64, u64, u64) {
+        (
+            self.files_deleted.load(Ordering::Relaxed),
+            self.dirs_deleted.load(Ordering::Relaxed),
+            self.errors_encountered.load(Ordering::Relaxed),
+            self.bytes_freed.load(Ordering::Relaxed),
+            self.skipped.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Number of files removed (or that would be removed in a dry run)
+    pub fn files_deleted(&self) -> u64 {
+        self.files_deleted.load(Ordering::Relaxed)
+    }
+
+    /// Number of directories removed (or that would be removed in a dry run)
+    pub fn dirs_deleted(&self) -> u64 {
+        self.dirs_deleted.load(Ordering::Relaxed)
+    }
+
+    /// Number of entries that could not be read or removed
+    pub fn errors_encountered(&self) -> u64 {
+        self.errors_encountered.load(Ordering::Relaxed)
+    }
+
+    /// Total size of the removed files in bytes
+    pub fn bytes_freed(&self) -> u64 {
+        self.bytes_freed.load(Ordering::Relaxed)
+    }
+
+    /// Number of errors that fall into `category`
+    pub fn errors_in(&self, category: ErrorCategory) -> u64 {
+        match category {
+            ErrorCategory::PermissionDenied => self.permission_errors.load(Ordering::Relaxed),
+            ErrorCategory::NotFound => self.not_found_errors.load(Ordering::Relaxed),
+            ErrorCategory::InUse => self.in_use_errors.load(Ordering::Relaxed),
+            ErrorCategory::Other => self.errors_encountered().saturating_sub(
+                self.permission_errors.load(Ordering::Relaxed)
+                    + self.not_found_errors.load(Ordering::Relaxed)
+                    + self.in_use_errors.load(Ordering::Relaxed),
+            ),
+        }
+    }
+
+    /// Every entry that could not be read or removed, with the error that
+    /// stopped it, in the order they were reported
+    ///
+    /// The errors are copies that keep the kind and OS error code, so callers
+    /// can classify them with [`ErrorCategory::of`] and decide what to retry.
+    pub fn failures(&self) -> Vec<(PathBuf, io::Error)> {
+        self.failures
+            .lock()
+            .map(|failures| {
+                failures
+                    .iter()
+                    .map(|(path, error)| (path.clone(), clone_io_error(error)))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Number of entries intentionally left in place by a filter
+    pub fn skipped(&self) -> u64 {
+        self.skipped.load(Ordering::Relaxed)
+    }
+
+    /// Number of directories left alone because they live on another filesystem
+    pub fn mounts_skipped(&self) -> u64 {
+        self.mounts_skipped.load(Ordering::Relaxed)
+    }
+
+    /// Number of files moved to the trash because deleting them failed
+    ///
+    /// Only possible with `DeleteOptions::trash_on_error`; these files are
+    /// also counted in [`DeletionStats::files_deleted`].
+    pub fn trash_fallbacks(&self) -> u64 {
+        self.trash_fallbacks.load(Ordering::Relaxed)
+    }
+
+    /// Number of targets moved into the `backup` directory with a single rename
+    pub fn backup_renames(&self) -> u64 {
+        self.backup_renames.load(Ordering::Relaxed)
+    }
+
+    /// Number of targets copied into the `backup` directory because it is on
+    /// another filesystem, and then deleted
+    pub fn backup_copies(&self) -> u64 {
+        self.backup_copies.load(Ordering::Relaxed)
+    }
+
+    /// Number of `keep_file` placeholders created in emptied roots
+    pub fn placeholders_created(&self) -> u64 {
+        self.placeholders_created.load(Ordering::Relaxed)
+    }
+
+    /// Most removals that were in flight at the same moment
+    ///
+    /// Removals hold a `jobs` permit, so this never exceeds `jobs`; staying
+    /// well below it means the traversal, not the limit, was the bottleneck.
+    pub fn peak_concurrency(&self) -> u64 {
+        self.peak_in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Number of filesystem calls issued: every removal attempt (retries
+    /// included), directory listing and metadata lookup
+    ///
+    /// A listing counts once, though the OS may need several calls to read a
+    /// large directory. Pre-scans such as `counted` progress aren't included.
+    pub fn syscalls(&self) -> u64 {
+        self.fs_calls.load(Ordering::Relaxed)
+    }
+
+    /// Number of entries still present after their removal reported success
+    ///
+    /// Only checked when `DeleteOptions::verify` is set.
+    pub fn verification_failures(&self) -> u64 {
+        self.verification_failures.load(Ordering::Relaxed)
+    }
+
+    /// The entries counted by [`DeletionStats::verification_failures`]
+    pub fn survivors(&self) -> Vec<PathBuf> {
+        self.survivors.lock().map(|s| s.clone()).unwrap_or_default()
+    }
+
+    /// Directories that already had no entries when they were listed, sorted
+    ///
+    /// Only collected with `DeleteOptions::report_empty`.
+    pub fn empty_dirs(&self) -> Vec<PathBuf> {
+        let mut empty_dirs = self.empty_dirs.lock().map(|e| e.clone()).unwrap_or_default();
+        empty_dirs.sort();
+        empty_dirs
+    }
+
+    /// Files found with extended attributes or ACLs, sorted
+    ///
+    /// Only collected with `DeleteOptions::warn_special`; the files are still
+    /// deleted.
+    pub fn special_files(&self) -> Vec<PathBuf> {
+        let mut special_files = self.special_files.lock().map(|s| s.clone()).unwrap_or_default();
+        special_files.sort();
+        special_files
+    }
+
+    /// FIFOs, sockets and device nodes left in place by
+    /// `DeleteOptions::skip_special`, sorted
+    pub fn special_kept(&self) -> Vec<PathBuf> {
+        let mut special_kept = self.special_kept.lock().map(|s| s.clone()).unwrap_or_default();
+        special_kept.sort();
+        special_kept
+    }
+
+    /// Targets that were emptied but kept because the directory itself can't
+    /// be removed, with the reason ("a mount point" or "a symlink")
+    pub fn kept_roots(&self) -> Vec<(PathBuf, &'static str)> {
+        self.kept_roots.lock().map(|k| k.clone()).unwrap_or_default()
+    }
+
+    /// Number of symlinks left in place by [`SymlinkPolicy::Skip`]
+    pub fn symlinks_skipped(&self) -> u64 {
+        self.symlinks_skipped.load(Ordering::Relaxed)
+    }
+
+    /// Per top-level subdirectory totals, largest first
+    ///
+    /// Only populated when `DeleteOptions::stats_by_dir` or
+    /// `DeleteOptions::profile` is set.
+    pub fn breakdown(&self) -> Vec<DirectoryBreakdown> {
+        let mut breakdown = self.breakdown.lock().map(|b| b.clone()).unwrap_or_default();
+        breakdown.sort_by_key(|entry| std::cmp::Reverse(entry.bytes));
+        breakdown
+    }
+
+    /// The `limit` top-level subdirectories that took longest, slowest first
+    ///
+    /// Sibling subtrees are deleted concurrently, so their times overlap and
+    /// can add up to more than the total run time.
+    pub fn slowest_directories(&self, limit: usize) -> Vec<DirectoryBreakdown> {
+        let mut breakdown = self.breakdown.lock().map(|b| b.clone()).unwrap_or_default();
+        breakdown.sort_by_key(|entry| std::cmp::Reverse(entry.elapsed));
+        breakdown.truncate(limit);
+        breakdown
+    }
+}
+
+/// How often the live throughput shown on a counted progress bar is recomputed
+const RATE_SAMPLE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Default cap on directory handles held open at once by the traversal
+///
+/// Well below the common 1024 descriptor limit, leaving room for the files
+/// being deleted and anything else the process has open.
+pub const DEFAULT_MAX_OPEN_DIRS: usize = 64;
+
+/// Suggested `simple_max_entries`, used by the command line
+///
+/// Below this many entries a blocking `remove_dir_all` beat the concurrent
+/// traversal in every benchmark run; see the README for the numbers.
+pub const DEFAULT_SIMPLE_MAX_ENTRIES: u64 = 2000;
+
+/// Most files `largest_first` plans at once; smaller files beyond this are
+/// left to the regular traversal, which keeps the plan's memory bounded
+const LARGEST_FIRST_MAX_FILES: usize = 100_000;
+
+/// Min-heap of `(size, path)` candidates kept by a `largest_first` plan
+type LargestFiles = BinaryHeap<Reverse<(u64, PathBuf)>>;
+
+/// Minimum time between two `--progress-stream` events
+const STREAM_EVENT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Files deleted since the last `--progress-stream` event that force a new one
+const STREAM_EVENT_FILES: u64 = 500;
+
+/// Windows error codes for a file held open or locked by another process
+#[cfg(windows)]
+const ERROR_SHARING_VIOLATION: i32 = 32;
+#[cfg(windows)]
+const ERROR_LOCK_VIOLATION: i32 = 33;
+
+/// Delay before the first retry of a transient failure; doubled on each attempt
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(10);
+
+/// Returns whether a failed removal is worth retrying
+///
+/// Permission errors are included because Windows reports files that are
+/// briefly held open by another process (antivirus, indexers) this way. On
+/// Windows the sharing and lock violation codes are matched explicitly too.
+fn is_transient(error: &io::Error) -> bool {
+    if error.kind() == io::ErrorKind::PermissionDenied {
+        return true;
+    }
+
+    #[cfg(windows)]
+    {
+        if matches!(error.raw_os_error(), Some(ERROR_SHARING_VIOLATION | ERROR_LOCK_VIOLATION)) {
+            return true;
+        }
+    }
+
+    false
+}
+
+/// Length at which paths get the extended-length prefix on Windows
+///
+/// `MAX_PATH` is 260, but directory APIs reserve room for an 8.3 file name,
+/// so the practical limit is 248.
+#[cfg(windows)]
+const LONG_PATH_THRESHOLD: usize = 248;
+
+/// Returns `path` in a form the Windows file APIs accept at any length
+///
+/// Absolute paths at or beyond [`LONG_PATH_THRESHOLD`] get the `\\?\` prefix
+/// (or `\\?\UNC\` for network shares), which lifts the `MAX_PATH` limit for
+/// the deeply nested child paths built during traversal. Short, relative or
+/// already-prefixed paths are returned unchanged, as is everything on other
+/// platforms.
+#[cfg(windows)]
+fn long_path(path: &Path) -> Cow<'_, Path> {
+    let raw = path.as_os_str();
+    if raw.len() < LONG_PATH_THRESHOLD || !path.is_absolute() {
+        return Cow::Borrowed(path);
+    }
+
+    let text = raw.to_string_lossy();
+    if text.starts_with(r"\\?\") || text.starts_with(r"\\.\") {
+        return Cow::Borrowed(path);
+    }
+
+    let mut prefixed = std::ffi::OsString::new();
+    match text.strip_prefix(r"\\") {
+        Some(share) => {
+            prefixed.push(r"\\?\UNC\");
+            prefixed.push(share);
+        }
+        None => {
+            prefixed.push(r"\\?\");
+            prefixed.push(raw);
+        }
+    }
+    Cow::Owned(PathBuf::from(prefixed))
+}
+
+#[cfg(not(windows))]
+fn long_path(path: &Path) -> Cow<'_, Path> {
+    Cow::Borrowed(path)
+}
+
+/// Returns whether an entry is a link that must be removed rather than entered
+///
+/// On Windows every reparse point counts, including junctions (directory
+/// mount points), which `npm` and `pnpm` create inside `node_modules`.
+#[cfg(windows)]
+fn is_link(metadata: &std::fs::Metadata) -> bool {
+    use std::os::windows::fs::MetadataExt;
+    use windows_sys::Win32::Storage::FileSystem::FILE_ATTRIBUTE_REPARSE_POINT;
+
+    metadata.file_type().is_symlink() || metadata.file_attributes() & FILE_ATTRIBUTE_REPARSE_POINT != 0
+}
+
+#[cfg(not(windows))]
+fn is_link(metadata: &std::fs::Metadata) -> bool {
+    metadata.file_type().is_symlink()
+}
+
+/// Names the kind of a FIFO, socket or device node, or `None` for anything else
+#[cfg(unix)]
+fn special_kind(metadata: &std::fs::Metadata) -> Option<&'static str> {
+    use std::os::unix::fs::FileTypeExt;
+
+    let file_type = metadata.file_type();
+    if file_type.is_fifo() {
+        Some("FIFO")
+    } else if file_type.is_socket() {
+        Some("socket")
+    } else if file_type.is_block_device() {
+        Some("block device")
+    } else if file_type.is_char_device() {
+        Some("character device")
+    } else {
+        None
+    }
+}
+
+#[cfg(not(unix))]
+fn special_kind(_metadata: &std::fs::Metadata) -> Option<&'static str> {
+    None
+}
+
+/// Removes a file or link without following it
+///
+/// Windows only removes directory links (junctions and directory symlinks)
+/// with `RemoveDirectory`, so a failed file removal is retried that way when
+/// the entry turns out to be one. The junction itself goes, never its target.
+#[cfg(windows)]
+fn remove_file_or_link_blocking(path: &Path) -> io::Result<()> {
+    use std::os::windows::fs::MetadataExt;
+    use windows_sys::Win32::Storage::FileSystem::FILE_ATTRIBUTE_DIRECTORY;
+
+    let path = long_path(path);
+    match std::fs::remove_file(&path) {
+        Err(e) => match std::fs::symlink_metadata(&path) {
+            Ok(metadata) if is_link(&metadata) && metadata.file_attributes() & FILE_ATTRIBUTE_DIRECTORY != 0 => {
+                std::fs::remove_dir(&path)
+            }
+            _ => Err(e),
+        },
+        result => result,
+    }
+}
+
+#[cfg(not(windows))]
+fn remove_file_or_link_blocking(path: &Path) -> io::Result<()> {
+    std::fs::remove_file(path)
+}
+
+/// [`remove_file_or_link_blocking`] on tokio's blocking pool
+async fn remove_file_or_link(path: &Path) -> io::Result<()> {
+    spawn_fs(path, remove_file_or_link_blocking).await
+}
+
+/// Makes `path` removable after a permission-denied failure
+///
+/// On Windows the read-only attribute on the entry itself blocks deletion, so
+/// it is cleared. On Unix removal is governed by the parent directory, so the
+/// owner write bit is added there instead; see [`DeletionEngine::may_force`].
+#[cfg(windows)]
+fn make_removable_blocking(path: &Path) -> io::Result<()> {
+    let path = long_path(path);
+    let mut permissions = std::fs::symlink_metadata(&path)?.permissions();
+    #[allow(clippy::permissions_set_readonly_false)]
+    permissions.set_readonly(false);
+    std::fs::set_permissions(&path, permissions)
+}
+
+#[cfg(unix)]
+fn make_removable_blocking(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let Some(parent) = path.parent() else {
+        return Ok(());
+    };
+    let mut permissions = std::fs::metadata(parent)?.permissions();
+    permissions.set_mode(permissions.mode() | 0o200);
+    std::fs::set_permissions(parent, permissions)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn make_removable_blocking(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// [`make_removable_blocking`] on tokio's blocking pool
+async fn make_removable(path: &Path) -> io::Result<()> {
+    spawn_fs(path, make_removable_blocking).await
+}
+
+/// Runs a blocking filesystem call on tokio's blocking pool, as `tokio::fs` does
+async fn spawn_fs(path: &Path, call: fn(&Path) -> io::Result<()>) -> io::Result<()> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || call(&path))
+        .await
+        .unwrap_or_else(|e| Err(io::Error::other(e)))
+}
+
+/// Adds the owner read, write and search bits to a directory so it can be
+/// listed and emptied
+#[cfg(unix)]
+async fn make_listable(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut permissions = fs::metadata(path).await?.permissions();
+    permissions.set_mode(permissions.mode() | 0o700);
+    fs::set_permissions(path, permissions).await
+}
+
+/// Serializable snapshot of a finished run, used for machine-readable output
+#[derive(Debug, Clone, Serialize)]
+pub struct DeletionSummary {
+    pub files_deleted: u64,
+    pub dirs_deleted: u64,
+    pub errors: u64,
+    pub bytes_freed: u64,
+    pub duration_secs: f64,
+    pub files_per_sec: f64,
+    pub verification_failures: u64,
+}
+
+/// One line of `--progress-stream` output
+#[derive(Debug, Serialize)]
+struct StreamEvent {
+    files: u64,
+    bytes: u64,
+    elapsed: f64,
+}
+
+/// What a [`ProgressEvent`] reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressKind {
+    /// A file (or link) was deleted, or would be in a dry run
+    File,
+    /// An emptied directory was removed, or would be in a dry run
+    Directory,
+    /// A whole target was moved at once, to the trash or to `backup`, or
+    /// removed at once because it was small (see
+    /// [`DeleteOptions::simple_max_entries`])
+    Tree,
+    /// An entry could not be read or removed
+    Error,
+}
+
+/// One finished operation, passed to [`ProgressCallback`]
+#[derive(Debug, Clone, Copy)]
+pub struct ProgressEvent<'a> {
+    pub path: &'a Path,
+    pub kind: ProgressKind,
+    /// Entries this event accounts for: 1 for a file or directory, every file
+    /// and directory of a [`ProgressKind::Tree`], 0 for an error
+    pub entries: u64,
+    /// Bytes freed by this operation (0 for directories and errors)
+    pub bytes: u64,
+    /// The failure, for [`ProgressKind::Error`]
+    pub error: Option<&'a io::Error>,
+}
+
+/// Shared progress callback for [`DeleteOptions::on_progress`]
+///
+/// The callback runs synchronously on whichever runtime thread finished the
+/// operation, so with more than one job it is called concurrently from
+/// several threads, in no particular order across entries. It should return
+/// quickly; a slow callback stalls the deletion that reported the event.
+#[derive(Clone)]
+pub struct ProgressCallback(Arc<dyn Fn(&ProgressEvent<'_>) + Send + Sync>);
+
+impl ProgressCallback {
+    pub fn new(callback: impl Fn(&ProgressEvent<'_>) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(callback))
+    }
+}
+
+impl<'a> ProgressEvent<'a> {
+    fn file(path: &'a Path, bytes: u64) -> Self {
+        Self {
+            path,
+            kind: ProgressKind::File,
+            entries: 1,
+            bytes,
+            error: None,
+        }
+    }
+
+    fn directory(path: &'a Path) -> Self {
+        Self {
+            path,
+            kind: ProgressKind::Directory,
+            entries: 1,
+            bytes: 0,
+            error: None,
+        }
+    }
+
+    fn tree(path: &'a Path, totals: ScanTotals) -> Self {
+        Self {
+            path,
+            kind: ProgressKind::Tree,
+            entries: totals.files + totals.dirs,
+            bytes: totals.bytes,
+            error: None,
+        }
+    }
+
+    fn error(path: &'a Path, error: &'a io::Error) -> Self {
+        Self {
+            path,
+            kind: ProgressKind::Error,
+            entries: 0,
+            bytes: 0,
+            error: Some(error),
+        }
+    }
+}
+
+impl std::fmt::Debug for ProgressCallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ProgressCallback")
+    }
+}
+
+impl DeletionSummary {
+    /// Builds a summary from the final statistics and the run's wall-clock time
+    pub fn new(stats: &DeletionStats, duration: Duration) -> Self {
+        let (files, dirs, errors, bytes, _) = stats.get_summary();
+        let duration_secs = duration.as_secs_f64();
+        let files_per_sec = if duration_secs > 0.0 {
+            files as f64 / duration_secs
+        } else {
+            0.0
+        };
+
+        Self {
+            files_deleted: files,
+            dirs_deleted: dirs,
+            errors,
+            bytes_freed: bytes,
+            duration_secs,
+            files_per_sec,
+            verification_failures: stats.verification_failures(),
+        }
+    }
+}
+
+/// Totals gathered by walking a directory tree without modifying it
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ScanTotals {
+    pub files: u64,
+    pub dirs: u64,
+    pub bytes: u64,
+}
+
+/// Returns the space a file actually occupies on disk
+///
+/// On Unix this is the allocated block count, so sparse files (VM images,
+/// some caches) count only their written regions rather than their logical
+/// length. Elsewhere block information isn't available and the logical
+/// length is used.
+#[cfg(unix)]
+fn disk_usage(metadata: &std::fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+
+    // st_blocks is always in 512-byte units, whatever the filesystem block size
+    metadata.blocks() * 512
+}
+
+#[cfg(not(unix))]
+fn disk_usage(metadata: &std::fs::Metadata) -> u64 {
+    metadata.len()
+}
+
+/// Returns the space removing one link of a file frees, given the links of
+/// each multiply-linked file found so far
+///
+/// A file with several hard links keeps its data until the last link is
+/// gone, so its size is only counted once every link has been found; links
+/// elsewhere mean nothing is freed. `hardlinks` maps (device, inode) to the
+/// links found and the link count seen on the first of them, before any was
+/// removed. This is best-effort: it is Unix-only and assumes the found links
+/// are all removed.
+#[cfg(unix)]
+fn linked_usage(hardlinks: &mut HashMap<(u64, u64), (u64, u64)>, metadata: &std::fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+
+    if metadata.nlink() <= 1 {
+        return disk_usage(metadata);
+    }
+
+    let (found, links) = hardlinks.entry((metadata.dev(), metadata.ino())).or_insert((0, metadata.nlink()));
+    *found += 1;
+    if found == links {
+        disk_usage(metadata)
+    } else {
+        0
+    }
+}
+
+#[cfg(not(unix))]
+fn linked_usage(_hardlinks: &mut HashMap<(u64, u64), (u64, u64)>, metadata: &std::fs::Metadata) -> u64 {
+    disk_usage(metadata)
+}
+
+/// Recursively tallies the files, directories and bytes under `path`
+///
+/// The root directory itself is included in the directory count; a `path`
+/// that is not a directory counts as a single file. Symlinks are counted as
+/// files and never followed. Entries that can't be read are silently left out
+/// of the totals.
+pub async fn scan_tree(path: &Path) -> ScanTotals {
+    if let Ok(metadata) = fs::symlink_metadata(path).await {
+        if !metadata.is_dir() || is_link(&metadata) {
+            return ScanTotals {
+                files: 1,
+                bytes: disk_usage(&metadata),
+                ..ScanTotals::default()
+            };
+        }
+    }
+
+    let mut totals = ScanTotals {
+        dirs: 1,
+        ..ScanTotals::default()
+    };
+
+    let Ok(mut entries) = fs::read_dir(path).await else {
+        return totals;
+    };
+
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let entry_path = entry.path();
+        match fs::symlink_metadata(&entry_path).await {
+            Ok(metadata) if metadata.is_dir() && !is_link(&metadata) => {
+                let sub = Box::pin(scan_tree(&entry_path)).await;
+                totals.files += sub.files;
+                totals.dirs += sub.dirs;
+                totals.bytes += sub.bytes;
+            }
+            Ok(metadata) => {
+                totals.files += 1;
+                totals.bytes += disk_usage(&metadata);
+            }
+            Err(_) => {}
+        }
+    }
+
+    totals
+}
+
+/// Tallies `path` like [`scan_tree`], giving up once it holds more than `limit` entries
+///
+/// The root counts as one entry. Returns `None` as soon as the limit is
+/// passed or anything can't be read, so a huge tree costs only the first
+/// `limit` lookups.
+async fn scan_small_tree(path: &Path, limit: u64) -> Option<ScanTotals> {
+    let mut totals = ScanTotals {
+        dirs: 1,
+        ..ScanTotals::default()
+    };
+    let mut hardlinks = HashMap::new();
+    let mut pending = vec![path.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let mut entries = fs::read_dir(long_path(&dir)).await.ok()?;
+        while let Some(entry) = entries.next_entry().await.ok()? {
+            let metadata = fs::symlink_metadata(entry.path()).await.ok()?;
+            if metadata.is_dir() && !is_link(&metadata) {
+                totals.dirs += 1;
+                pending.push(entry.path());
+            } else {
+                totals.files += 1;
+                totals.bytes += linked_usage(&mut hardlinks, &metadata);
+            }
+            if totals.files + totals.dirs > limit {
+                return None;
+            }
+        }
+    }
+    Some(totals)
+}
+
+/// One entry of a [`preview_tree`] listing
+#[derive(Debug, Clone)]
+pub struct PreviewEntry {
+    pub path: PathBuf,
+    pub is_dir: bool,
+    /// Totals of everything under this entry, including levels not listed
+    pub totals: ScanTotals,
+    /// Listed children, largest first
+    pub children: Vec<PreviewEntry>,
+    /// Children left out because the directory was wider than the limit
+    pub hidden: usize,
+}
+
+/// Builds a size-annotated tree of `path` for a preview before deleting
+///
+/// Directories below `depth` levels are summarized without listing their
+/// children, and at most `width` children (the largest) are listed per
+/// directory; `None` lifts either limit. Sizes always cover the whole
+/// subtree, and symlinks are listed as files without being followed, as in
+/// [`scan_tree`].
+pub async fn preview_tree(path: &Path, depth: Option<usize>, width: Option<usize>) -> PreviewEntry {
+    let is_dir = fs::symlink_metadata(path)
+        .await
+        .is_ok_and(|metadata| metadata.is_dir() && !is_link(&metadata));
+    if !is_dir || depth == Some(0) {
+        return PreviewEntry {
+            path: path.to_path_buf(),
+            is_dir,
+            totals: scan_tree(path).await,
+            children: Vec::new(),
+            hidden: 0,
+        };
+    }
+
+    let mut totals = ScanTotals {
+        dirs: 1,
+        ..ScanTotals::default()
+    };
+    let mut children = Vec::new();
+    if let Ok(mut entries) = fs::read_dir(path).await {
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let child = Box::pin(preview_tree(&entry.path(), depth.map(|depth| depth - 1), width)).await;
+            totals.files += child.totals.files;
+            totals.dirs += child.totals.dirs;
+            totals.bytes += child.totals.bytes;
+            children.push(child);
+        }
+    }
+
+    // Largest first, with the name as a tie-breaker so the order is stable
+    children.sort_by(|a, b| b.totals.bytes.cmp(&a.totals.bytes).then_with(|| a.path.cmp(&b.path)));
+    let hidden = width.map_or(0, |width| children.len().saturating_sub(width));
+    children.truncate(children.len() - hidden);
+    PreviewEntry {
+        path: path.to_path_buf(),
+        is_dir,
+        totals,
+        children,
+        hidden,
+    }
+}
+
+/// Returns an identifier for the filesystem `path` lives on
+///
+/// On Unix this is `st_dev` from the already-fetched metadata. On Windows it is
+/// the volume serial number, which needs an extra handle open. Elsewhere the
+/// device can't be determined and `None` is returned.
+#[cfg(unix)]
+async fn device_id(_path: &Path, metadata: &std::fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.dev())
+}
+
+#[cfg(windows)]
+async fn device_id(path: &Path, _metadata: &std::fs::Metadata) -> Option<u64> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || volume_serial_number(&path).ok())
+        .await
+        .ok()
+        .flatten()
+}
+
+#[cfg(not(any(unix, windows)))]
+async fn device_id(_path: &Path, _metadata: &std::fs::Metadata) -> Option<u64> {
+    None
+}
+
+/// Explains why an emptied root directory can't be removed, if it can't
+///
+/// A symlink (only deleted through with [`SymlinkPolicy::Follow`]) isn't a
+/// directory to `remove_dir`, and a mount point can't be removed while it is
+/// mounted; both would otherwise fail with a confusing error at the very end.
+/// A mount point is detected by its device differing from its parent's.
+async fn unremovable_root(path: &Path, metadata: &std::fs::Metadata) -> Option<&'static str> {
+    if fs::symlink_metadata(path).await.is_ok_and(|metadata| is_link(&metadata)) {
+        return Some("a symlink");
+    }
+
+    let parent = match path.parent()? {
+        parent if parent.as_os_str().is_empty() => Path::new("."),
+        parent => parent,
+    };
+    let parent_metadata = fs::metadata(parent).await.ok()?;
+    let device = device_id(path, metadata).await?;
+    let parent_device = device_id(parent, &parent_metadata).await?;
+    (device != parent_device).then_some("a mount point")
+}
+
+/// Reads the volume serial number of the volume holding `path`
+#[cfg(windows)]
+fn volume_serial_number(path: &Path) -> io::Result<u64> {
+    use std::os::windows::fs::OpenOptionsExt;
+    use std::os::windows::io::AsRawHandle;
+    use windows_sys::Win32::Storage::FileSystem::{
+        GetFileInformationByHandle, BY_HANDLE_FILE_INFORMATION, FILE_FLAG_BACKUP_SEMANTICS,
+    };
+
+    // Backup semantics are required to open a handle to a directory
+    let file = std::fs::OpenOptions::new()
+        .access_mode(0)
+        .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+        .open(path)?;
+
+    // SAFETY: the handle is valid for the lifetime of `file` and `info` is a
+    // plain-old-data out parameter.
+    let mut info: BY_HANDLE_FILE_INFORMATION = unsafe { std::mem::zeroed() };
+    if unsafe { GetFileInformationByHandle(file.as_raw_handle(), &mut info) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(u64::from(info.dwVolumeSerialNumber))
+}
+
+/// Space available on the filesystem holding a path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FreeSpace {
+    /// Identifies the filesystem, so paths on the same one can be reported
+    /// once; `None` where it can't be determined
+    pub device: Option<u64>,
+    /// Bytes available to the current user
+    pub available: u64,
+}
+
+/// Queries the free space on the filesystem holding `path`
+///
+/// If `path` doesn't exist (for instance because it was just deleted) its
+/// nearest existing ancestor is queried instead, which lives on the same
+/// filesystem unless the path was itself a mount point.
+pub async fn free_space(path: &Path) -> io::Result<FreeSpace> {
+    let mut probe = path;
+    let metadata = loop {
+        match fs::metadata(probe).await {
+            Ok(metadata) => break metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => match probe.parent() {
+                Some(parent) => probe = parent,
+                None => return Err(e),
+            },
+            Err(e) => return Err(e),
+        }
+    };
+
+    let device = device_id(probe, &metadata).await;
+    let target = probe.to_path_buf();
+    let available = tokio::task::spawn_blocking(move || available_space(&target))
+        .await
+        .map_err(io::Error::other)??;
+    Ok(FreeSpace { device, available })
+}
+
+/// Extended attributes so common on some systems (SELinux labels, macOS
+/// download markers) that they don't make a file special
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+const COMMON_XATTRS: &[&[u8]] = &[b"security.selinux", b"com.apple.provenance", b"com.apple.quarantine"];
+
+/// Returns whether `path` carries extended attributes or POSIX ACLs (which
+/// are stored as `system.posix_acl_*` attributes), ignoring [`COMMON_XATTRS`]
+///
+/// Symlinks are inspected themselves, not their targets. Filesystems without
+/// extended attribute support report none.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn has_special_xattrs(path: &Path) -> io::Result<bool> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes()).map_err(io::Error::other)?;
+    let mut names = Vec::new();
+    loop {
+        let len = list_xattrs(&path, &mut names);
+        if len < 0 {
+            let error = io::Error::last_os_error();
+            match error.raw_os_error() {
+                // The list grew since it was sized; size it again
+                Some(libc::ERANGE) => names.clear(),
+                Some(libc::ENOTSUP) => return Ok(false),
+                _ => return Err(error),
+            }
+            continue;
+        }
+        let len = len as usize;
+        if len <= names.len() {
+            names.truncate(len);
+            break;
+        }
+        names.resize(len, 0);
+    }
+
+    Ok(names
+        .split(|&byte| byte == 0)
+        .any(|name| !name.is_empty() && !COMMON_XATTRS.contains(&name)))
+}
+
+/// Lists the NUL-separated attribute names of `path` into `buf`, or returns
+/// the size needed when `buf` is empty
+#[cfg(target_os = "linux")]
+fn list_xattrs(path: &std::ffi::CStr, buf: &mut [u8]) -> isize {
+    // SAFETY: `path` is NUL-terminated and `buf` is valid for `buf.len()` bytes.
+    unsafe { libc::llistxattr(path.as_ptr(), buf.as_mut_ptr().cast(), buf.len()) }
+}
+
+#[cfg(target_os = "macos")]
+fn list_xattrs(path: &std::ffi::CStr, buf: &mut [u8]) -> isize {
+    // SAFETY: `path` is NUL-terminated and `buf` is valid for `buf.len()` bytes.
+    unsafe { libc::listxattr(path.as_ptr(), buf.as_mut_ptr().cast(), buf.len(), libc::XATTR_NOFOLLOW) }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn has_special_xattrs(_path: &Path) -> io::Result<bool> {
+    Ok(false)
+}
+
+#[cfg(unix)]
+fn available_space(path: &Path) -> io::Result<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes()).map_err(io::Error::other)?;
+    // SAFETY: `path` is a valid NUL-terminated string and `stats` is a
+    // plain-old-data out parameter.
+    let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stats) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    #[allow(clippy::unnecessary_cast)] // The field widths differ between platforms
+    Ok(stats.f_bavail as u64 * stats.f_frsize as u64)
+}
+
+#[cfg(windows)]
+fn available_space(path: &Path) -> io::Result<u64> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+
+    let wide: Vec<u16> = path.as_os_str().encode_wide().chain(std::iter::once(0)).collect();
+    let mut available = 0u64;
+    // SAFETY: `wide` is NUL-terminated and outlives the call; the unused
+    // totals are allowed to be null.
+    let ok = unsafe { GetDiskFreeSpaceExW(wide.as_ptr(), &mut available, std::ptr::null_mut(), std::ptr::null_mut()) };
+    if ok == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(available)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn available_space(_path: &Path) -> io::Result<u64> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "free space can't be queried on this platform"))
+}
+
+/// Moves a single entry to the OS trash on the blocking threadpool
+async fn move_to_trash(path: &Path) -> io::Result<()> {
+    let target = path.to_path_buf();
+    tokio::task::spawn_blocking(move || trash::delete(&target))
+        .await
+        .map_err(io::Error::other)?
+        .map_err(io::Error::other)
+}
+
+/// Picks a path in `backup_dir` named after `path` that doesn't exist yet
+///
+/// Targets with the same name get `-1`, `-2`, ... appended.
+async fn unique_destination(backup_dir: &Path, path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or("target".as_ref());
+    let mut destination = backup_dir.join(name);
+    let mut suffix = 1;
+    while fs::symlink_metadata(&destination).await.is_ok() {
+        let mut numbered = name.to_os_string();
+        numbered.push(format!("-{}", suffix));
+        destination = backup_dir.join(numbered);
+        suffix += 1;
+    }
+    destination
+}
+
+/// Copies a file or directory tree from `source` to `target`
+///
+/// Symlinks are recreated rather than followed; on Windows, directory links
+/// (including junctions) become directory symlinks.
+fn copy_tree(source: &Path, target: &Path) -> io::Result<()> {
+    let metadata = std::fs::symlink_metadata(source)?;
+    if is_link(&metadata) {
+        return copy_link(source, target, &metadata);
+    }
+    if !metadata.is_dir() {
+        return std::fs::copy(source, target).map(|_| ());
+    }
+
+    std::fs::create_dir(target)?;
+    for entry in std::fs::read_dir(source)? {
+        let entry = entry?;
+        copy_tree(&entry.path(), &target.join(entry.file_name()))?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn copy_link(source: &Path, target: &Path, _metadata: &std::fs::Metadata) -> io::Result<()> {
+    std::os::unix::fs::symlink(std::fs::read_link(source)?, target)
+}
+
+#[cfg(windows)]
+fn copy_link(source: &Path, target: &Path, metadata: &std::fs::Metadata) -> io::Result<()> {
+    use std::os::windows::fs::MetadataExt;
+    use windows_sys::Win32::Storage::FileSystem::FILE_ATTRIBUTE_DIRECTORY;
+
+    let link = std::fs::read_link(source)?;
+    if metadata.file_attributes() & FILE_ATTRIBUTE_DIRECTORY != 0 {
+        std::os::windows::fs::symlink_dir(link, target)
+    } else {
+        std::os::windows::fs::symlink_file(link, target)
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn copy_link(source: &Path, _target: &Path, _metadata: &std::fs::Metadata) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("Can't copy symlink {}", escape_path(&source)),
+    ))
+}
+
+/// Fails if `path` itself is a symlink, naming the entry it points to
+///
+/// Deleting "through" a link would remove the target's contents rather than
+/// the link, so callers refuse unless following symlinks was requested.
+pub async fn refuse_symlink_root(path: &Path) -> Result<()> {
+    let is_symlink = fs::symlink_metadata(path).await.is_ok_and(|metadata| is_link(&metadata));
+    if is_symlink {
+        let destination = fs::read_link(path)
+            .await
+            .map_or_else(|_| "an unreadable target".to_string(), |target| escape_path(&target).to_string());
+        anyhow::bail!(
+            "Refusing to delete through symlink {} -> {} (use --dereference-root-only to delete what it points to)",
+            escape_path(path),
+            destination
+        );
+    }
+    Ok(())
+}
+
+/// Fails if `path` ends in a path separator but names a file
+///
+/// Canonicalizing `file.txt/` fails with a bare OS error on Unix and may
+/// behave differently on Windows, which treats `/` and `\` alike and is
+/// lenient about trailing separators. Checking up front gives one clear
+/// message on every platform. Paths that don't exist are left to the
+/// caller's own error handling.
+pub async fn refuse_file_as_directory(path: &Path) -> Result<()> {
+    let has_trailing_separator = path
+        .as_os_str()
+        .to_string_lossy()
+        .ends_with(std::path::is_separator);
+    if !has_trailing_separator {
+        return Ok(());
+    }
+
+    // Rebuilding from the components drops the trailing separators
+    let stripped: PathBuf = path.components().collect();
+    if fs::metadata(&stripped).await.is_ok_and(|metadata| !metadata.is_dir()) {
+        anyhow::bail!(
+            "Not a directory: {} is a file (drop the trailing separator to delete it)",
+            escape_path(path)
+        );
+    }
+    Ok(())
+}
+
+/// Shows a path with bytes that aren't valid UTF-8 escaped as `\xNN`
+///
+/// `Path::display` turns every invalid sequence into the same replacement
+/// character, so two different non-UTF-8 names would look alike in an error
+/// list. Valid paths are shown exactly as `display` would show them.
+pub fn escape_path(path: &Path) -> EscapedPath<'_> {
+    EscapedPath(path)
+}
+
+/// [`Display`](std::fmt::Display) wrapper returned by [`escape_path`]
+#[derive(Debug, Clone, Copy)]
+pub struct EscapedPath<'a>(&'a Path);
+
+impl std::fmt::Display for EscapedPath<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for chunk in self.0.as_os_str().as_encoded_bytes().utf8_chunks() {
+            f.write_str(chunk.valid())?;
+            for byte in chunk.invalid() {
+                write!(f, "\\x{:02X}", byte)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns whether deleting `target` would remove the directory `dir`
+///
+/// Both paths are canonicalized first, so `.`, `..` and symlinked aliases
+/// are compared by what they resolve to. Used to catch deleting the current
+/// working directory, which leaves a Unix shell in a removed directory and
+/// fails on Windows because the directory is in use. Paths that can't be
+/// resolved never match.
+pub fn contains_dir(target: &Path, dir: &Path) -> bool {
+    match (std::fs::canonicalize(target), std::fs::canonicalize(dir)) {
+        (Ok(target), Ok(dir)) => dir.starts_with(target),
+        _ => false,
+    }
+}
+
+/// One line of a `--manifest` file
+#[derive(Debug, Serialize)]
+struct ManifestEntry {
+    path: String,
+    size: u64,
+    /// RFC 3339 modification time, `null` if the platform can't report it
+    mtime: Option<String>,
+    /// Hex BLAKE3 hash of the contents, only written by
+    /// [`write_checksum_manifest`]; `null` for links, other special files and
+    /// unreadable files
+    #[serde(skip_serializing_if = "Option::is_none")]
+    blake3: Option<Option<String>>,
+}
+
+/// The part of a manifest line that `--resume` reads back
+#[derive(Debug, Deserialize)]
+struct ListedPath {
+    path: String,
+}
+
+/// Writes a newline-delimited JSON listing of every file under `targets`
+///
+/// Each line is `{"path":...,"size":...,"mtime":...}`. Symlinks are listed
+/// as files and never followed; entries that can't be read are left out.
+/// The file is flushed before returning, so calling this before deleting
+/// anything yields a faithful snapshot. Returns the number of files listed.
+pub async fn write_manifest(targets: &[PathBuf], manifest: &Path) -> Result<u64> {
+    write_manifest_with(targets, manifest, None).await
+}
+
+/// Like [`write_manifest`], but also records a BLAKE3 hash of each file
+///
+/// Every file is read in full, up to `jobs` at a time, which usually costs
+/// far more than deleting them. Lines gain a `"blake3"` field holding the hex
+/// hash, or `null` for symlinks, FIFOs, devices and files that can't be read.
+pub async fn write_checksum_manifest(targets: &[PathBuf], manifest: &Path, jobs: usize) -> Result<u64> {
+    write_manifest_with(targets, manifest, Some(jobs.max(1))).await
+}
+
+async fn write_manifest_with(targets: &[PathBuf], manifest: &Path, checksum_jobs: Option<usize>) -> Result<u64> {
+    let file = fs::File::create(manifest)
+        .await
+        .with_context(|| format!("Failed to create manifest: {}", escape_path(manifest)))?;
+    let mut writer = BufWriter::new(file);
+    let mut listed = 0;
+    for target in targets {
+        listed += Box::pin(write_manifest_entries(target, &mut writer, checksum_jobs)).await?;
+    }
+    writer.flush().await.context("Failed to write manifest")?;
+    Ok(listed)
+}
+
+/// Lists `path` and everything below it, one directory at a time
+///
+/// The files of a directory are written before its subdirectories are
+/// entered, so with `checksum_jobs` they can be hashed concurrently while
+/// the lines still come out in listing order.
+async fn write_manifest_entries(
+    path: &Path,
+    writer: &mut BufWriter<fs::File>,
+    checksum_jobs: Option<usize>,
+) -> Result<u64> {
+    let Ok(metadata) = fs::symlink_metadata(path).await else {
+        return Ok(0);
+    };
+
+    if !metadata.is_dir() || is_link(&metadata) {
+        let line = manifest_line(path, &metadata, checksum_jobs.is_some()).await?;
+        writer.write_all(&line).await.context("Failed to write manifest")?;
+        return Ok(1);
+    }
+
+    let Ok(mut entries) = fs::read_dir(path).await else {
+        return Ok(0);
+    };
+
+    let mut files = Vec::new();
+    let mut subdirs = Vec::new();
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let path = entry.path();
+        let Ok(metadata) = fs::symlink_metadata(&path).await else {
+            continue;
+        };
+        if metadata.is_dir() && !is_link(&metadata) {
+            subdirs.push(path);
+        } else {
+            files.push((path, metadata));
+        }
+    }
+
+    let mut listed = 0;
+    let mut lines = stream::iter(&files)
+        .map(|(path, metadata)| manifest_line(path, metadata, checksum_jobs.is_some()))
+        .buffered(checksum_jobs.unwrap_or(1));
+    while let Some(line) = lines.next().await {
+        writer.write_all(&line?).await.context("Failed to write manifest")?;
+        listed += 1;
+    }
+    for subdir in subdirs {
+        listed += Box::pin(write_manifest_entries(&subdir, writer, checksum_jobs)).await?;
+    }
+    Ok(listed)
+}
+
+/// Serializes the manifest line of one file, hashing it if `checksum` is set
+async fn manifest_line(path: &Path, metadata: &std::fs::Metadata, checksum: bool) -> Result<Vec<u8>> {
+    let blake3 = if checksum {
+        // Only regular files: opening a FIFO would block forever
+        Some(if metadata.is_file() { hash_file(path).await } else { None })
+    } else {
+        None
+    };
+    let entry = ManifestEntry {
+        path: escape_path(path).to_string(),
+        size: metadata.len(),
+        mtime: metadata
+            .modified()
+            .ok()
+            .map(|mtime| humantime::format_rfc3339_seconds(mtime).to_string()),
+        blake3,
+    };
+    let mut line = serde_json::to_vec(&entry)?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+/// Returns the hex BLAKE3 hash of a file's contents, or `None` if it can't be read
+async fn hash_file(path: &Path) -> Option<String> {
+    let path = long_path(path).into_owned();
+    tokio::task::spawn_blocking(move || {
+        let file = std::fs::File::open(path).ok()?;
+        let mut hasher = blake3::Hasher::new();
+        hasher.update_reader(file).ok()?;
+        Some(hasher.finalize().to_hex().to_string())
+    })
+    .await
+    .ok()
+    .flatten()
+}
+
+/// Reverses [`escape_path`], or `None` if `text` has nothing to decode
+///
+/// A valid UTF-8 name can itself contain `\xNN`, so callers try the literal
+/// path first and only fall back to this spelling.
+#[cfg(unix)]
+fn unescape_path(text: &str) -> Option<PathBuf> {
+    use std::os::unix::ffi::OsStringExt;
+
+    if !text.contains("\\x") {
+        return None;
+    }
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let decoded = tail
+            .strip_prefix(b"x")
+            .and_then(|hex| hex.get(..2))
+            .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match (byte, decoded) {
+            (b'\\', Some(decoded)) => {
+                bytes.push(decoded);
+                rest = &tail[3..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    Some(PathBuf::from(std::ffi::OsString::from_vec(bytes)))
+}
+
+/// Escaped names only come from invalid UTF-8, which Windows paths can't hold
+#[cfg(not(unix))]
+fn unescape_path(_text: &str) -> Option<PathBuf> {
+    None
+}
+
+/// Compiles exclusion patterns into a single matcher
+fn build_glob_set(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = Glob::new(pattern)
+            .with_context(|| format!("Invalid glob pattern: {}", pattern))?;
+        builder.add(glob);
+    }
+    Ok(builder.build()?)
+}
+
+/// Matches `path` by its path relative to `root` or by its bare file name
+fn matches_glob_set(globs: &GlobSet, root: &Path, path: &Path) -> bool {
+    if globs.is_empty() {
+        return false;
+    }
+
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    globs.is_match(relative) || path.file_name().is_some_and(|name| globs.is_match(name))
+}
+
+/// Options controlling a deletion run
+///
+/// This is the library-level counterpart of the CLI flags. `Default` gives a
+/// quiet, permanent delete with one job per CPU and no filters.
+#[derive(Debug, Clone)]
+pub struct DeleteOptions {
+    /// Show a spinner with the entry currently being deleted
+    ///
+    /// Per-entry messages and failures are emitted as `tracing` events
+    /// regardless; install a subscriber to see them.
+    pub verbose: bool,
+    /// Called for every deleted entry and every failure, for embedders that
+    /// draw their own progress instead of the built-in `indicatif` bar
+    ///
+    /// The built-in bar and `progress_stream` are driven from the same
+    /// events. See [`ProgressCallback`] for the threading rules.
+    pub on_progress: Option<ProgressCallback>,
+    /// Maximum number of concurrent delete operations; must be at least 1
+    pub jobs: usize,
+    /// Maximum number of directories being listed at once; must be at least 1
+    ///
+    /// This is separate from `jobs` so a wide, deep tree can't exhaust file
+    /// descriptors; lower it if "too many open files" errors show up.
+    pub max_open_dirs: usize,
+    /// Remove targets with at most this many entries in one blocking
+    /// `std::fs::remove_dir_all` call instead of the concurrent traversal
+    ///
+    /// Spawning tasks per entry costs more than it saves on small trees. A
+    /// bounded pre-scan decides, and only plain deletions qualify: any
+    /// filter, `dry_run`, `verify`, `keep_root`, a log file or progress
+    /// callback (which expect every entry reported) selects the traversal.
+    /// If the call fails partway, the traversal takes over what's left.
+    /// 0, the default, turns this off; see [`DEFAULT_SIMPLE_MAX_ENTRIES`].
+    pub simple_max_entries: u64,
+    /// Walk the tree and update statistics without removing anything
+    pub dry_run: bool,
+    /// Move entries to the OS trash instead of deleting them permanently
+    pub trash: bool,
+    /// Move a file to the OS trash when deleting it permanently fails
+    ///
+    /// The fallback happens once the file's retries are used up. Trashed files
+    /// count as deleted but not toward the bytes freed. Has no effect
+    /// together with `trash`.
+    pub trash_on_error: bool,
+    /// Glob patterns for entries to keep
+    pub excludes: Vec<String>,
+    /// Glob patterns restricting deletion to matching entries; empty deletes everything
+    ///
+    /// A matching directory is deleted as a whole. Other directories are
+    /// searched for matches and only removed once emptied. `excludes` always
+    /// take precedence over `includes`.
+    pub includes: Vec<String>,
+    /// Regular expression matched against each file's full path; only
+    /// matching files are deleted
+    ///
+    /// Matching is case-sensitive unless the pattern starts with `(?i)`, and
+    /// the path is searched, not anchored, so `\.log$` matches any `.log`
+    /// file. Directories are still entered and removed once emptied.
+    pub path_filter: Option<String>,
+    /// Delete only the files `path_filter` does *not* match
+    pub path_filter_invert: bool,
+    /// Deepest directory level to descend into; `Some(0)` only deletes the
+    /// files directly inside the root and keeps every subdirectory
+    pub max_depth: Option<usize>,
+    /// Empty the target directory but leave the directory itself in place
+    pub keep_root: bool,
+    /// Create an empty placeholder file with this name (e.g. `.gitkeep`) in
+    /// each emptied root; requires `keep_root`
+    ///
+    /// It is only created once the root was emptied completely, so nothing is
+    /// created if an entry was kept or failed. Ignored in dry-run mode.
+    pub keep_file: Option<String>,
+    /// Record directories that had no entries at all when they were listed
+    ///
+    /// They are available from [`DeletionStats::empty_dirs`] afterwards.
+    pub report_empty: bool,
+    /// Warn about, and record, files carrying extended attributes or POSIX
+    /// ACLs before they are removed
+    ///
+    /// Costs an extra syscall per file, so it is off by default. Only Linux
+    /// and macOS are checked; see [`DeletionStats::special_files`].
+    pub warn_special: bool,
+    /// Leave FIFOs, sockets and block or character devices in place instead
+    /// of unlinking them like files
+    ///
+    /// They are available from [`DeletionStats::special_kept`] afterwards.
+    /// Only Unix has these file types.
+    pub skip_special: bool,
+    /// What to do with symlinks inside the target; defaults to unlinking them
+    ///
+    /// A target that is itself a symlink is refused unless this is
+    /// [`SymlinkPolicy::Follow`] or `unlink_root_symlink` or
+    /// `dereference_root` is set.
+    pub on_symlink: SymlinkPolicy,
+    /// Resolve a target that is itself a symlink and delete the directory
+    /// (or file) it points to, while symlinks inside it are still handled by
+    /// `on_symlink`
+    ///
+    /// The link itself is left in place. Can't be combined with
+    /// `unlink_root_symlink`.
+    pub dereference_root: bool,
+    /// Remove a target that is itself a symlink, including a broken one, as
+    /// a link instead of refusing it
+    ///
+    /// Has no effect with [`SymlinkPolicy::Follow`], which deletes through
+    /// the link, or with `backup`, which still refuses it.
+    pub unlink_root_symlink: bool,
+    /// Never descend into a directory on a different filesystem than the root
+    pub one_file_system: bool,
+    /// Pre-scan each target and show a progress bar with a real total and ETA
+    /// instead of the verbose spinner
+    pub progress: bool,
+    /// `indicatif` template replacing the default progress bar or spinner
+    /// layout, e.g. `"{spinner} {pos}/{len} {msg}"`
+    pub progress_template: Option<String>,
+    /// How many times to retry a removal that failed with a transient error
+    pub retries: u32,
+    /// On permission errors, clear read-only attributes and try once more
+    ///
+    /// On Unix this adds the owner write bit to the entry's parent, so it is
+    /// only done for entries below a target, never for the target itself.
+    pub force: bool,
+    /// Only delete files last modified longer ago than this
+    pub older_than: Option<Duration>,
+    /// Only delete files last modified more recently than this
+    ///
+    /// Together with `older_than` this selects a window, so it must be the
+    /// longer of the two.
+    pub newer_than: Option<Duration>,
+    /// Only delete files strictly larger than this many bytes
+    pub larger_than: Option<u64>,
+    /// Only delete files strictly smaller than this many bytes
+    pub smaller_than: Option<u64>,
+    /// Keep every file strictly larger than this many bytes
+    ///
+    /// Unlike `smaller_than`, a kept file is reported as protected, and its
+    /// parent directories are left in place around it.
+    pub exclude_larger_than: Option<u64>,
+    /// Stop starting new deletions once this many bytes have been freed
+    ///
+    /// Deletions already in flight still finish, so the final total usually
+    /// overshoots the threshold a little.
+    pub min_free: Option<u64>,
+    /// Track files, bytes and time per top-level subdirectory of each target
+    pub stats_by_dir: bool,
+    /// Time each top-level subdirectory so the slowest can be reported
+    pub profile: bool,
+    /// Only delete entries matched by `.gitignore` rules, like `git clean -X`
+    ///
+    /// Every `.gitignore` inside the target (including the target's own) is
+    /// honored, with deeper files taking precedence. Rules from directories
+    /// above the target are not consulted.
+    pub gitignore: bool,
+    /// Only remove directories that are empty or become empty; files are never touched
+    pub empty_dirs_only: bool,
+    /// Write throttled JSON progress events to stderr for frontends to consume
+    pub progress_stream: bool,
+    /// Ask on stdin before deleting each immediate subdirectory of a target
+    ///
+    /// Files directly inside the target are deleted without asking.
+    pub interactive: bool,
+    /// Append a timestamped record of every deletion and error to this file
+    pub log_file: Option<PathBuf>,
+    /// Abort the run with an error on the first entry that can't be read or removed
+    ///
+    /// By default failures are counted and the rest of the tree is still deleted.
+    pub fail_fast: bool,
+    /// Re-stat every removed entry (including the root) to confirm it is gone
+    ///
+    /// Survivors are counted in [`DeletionStats::verification_failures`].
+    /// Ignored in dry-run mode.
+    pub verify: bool,
+    /// Delete the largest files of each target first, to recover space as
+    /// quickly as possible
+    ///
+    /// A planning pass holds up to 100,000 of the largest candidates in
+    /// memory (roughly 100-200 bytes each); smaller files and directories are
+    /// removed by the regular traversal afterwards. Ignored in dry-run mode
+    /// and together with `interactive`.
+    pub largest_first: bool,
+    /// Remove at most this many files per second, across all jobs; must be at least 1
+    ///
+    /// `jobs` still bounds how many removals run at once, but the rate caps
+    /// the total, so raising `jobs` won't go faster than this.
+    pub max_rate: Option<u32>,
+    /// Whether files, sibling directories or both are processed concurrently
+    ///
+    /// `jobs` still caps the total number of removals in flight.
+    pub concurrency_model: ConcurrencyModel,
+    /// Whether directories are listed depth-first or breadth-first
+    pub delete_order: DeleteOrder,
+    /// Walk each tree with `std::fs` on a work-stealing pool of `jobs`
+    /// threads, shared by every target of the engine, instead of with
+    /// `tokio::fs`
+    ///
+    /// Saves a hop to tokio's blocking pool per filesystem call, which can
+    /// help metadata-heavy trees. `concurrency_model`, `delete_order` and
+    /// `max_open_dirs` don't apply; each thread holds at most one directory
+    /// open. Can't be combined with options that need the async engine.
+    pub blocking: bool,
+    /// Move each target into a timestamped folder under this directory
+    /// instead of deleting it
+    ///
+    /// A rename is used when the directory is on the same filesystem, and a
+    /// copy followed by a delete otherwise. Whole targets are moved, so this
+    /// can't be combined with `trash`, `keep_root` or any filter. Ignored in
+    /// dry-run mode.
+    pub backup: Option<PathBuf>,
+    /// Skip the per-entry `lstat` and classify entries by the type the
+    /// directory listing already reports (unsafe)
+    ///
+    /// No sizes are measured, so [`DeletionStats::bytes_freed`] stays zero,
+    /// and every option that needs metadata or keeps entries inside the
+    /// tree is refused. Only `excludes` still applies. Meant for throwaway
+    /// directories where speed is all that matters.
+    pub assume_empty_fast_delete: bool,
+}
+
+impl Default for DeleteOptions {
+    fn default() -> Self {
+        Self {
+            verbose: false,
+            on_progress: None,
+            jobs: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            max_open_dirs: DEFAULT_MAX_OPEN_DIRS,
+            simple_max_entries: 0,
+            dry_run: false,
+            trash: false,
+            trash_on_error: false,
+            excludes: Vec::new(),
+            includes: Vec::new(),
+            path_filter: None,
+            path_filter_invert: false,
+            max_depth: None,
+            keep_root: false,
+            keep_file: None,
+            report_empty: false,
+            warn_special: false,
+            skip_special: false,
+            on_symlink: SymlinkPolicy::default(),
+            unlink_root_symlink: false,
+            dereference_root: false,
+            one_file_system: false,
+            progress: false,
+            progress_template: None,
+            retries: 3,
+            force: false,
+            older_than: None,
+            newer_than: None,
+            larger_than: None,
+            smaller_than: None,
+            exclude_larger_than: None,
+            min_free: None,
+            stats_by_dir: false,
+            profile: false,
+            gitignore: false,
+            empty_dirs_only: false,
+            progress_stream: false,
+            interactive: false,
+            log_file: None,
+            fail_fast: false,
+            verify: false,
+            largest_first: false,
+            max_rate: None,
+            concurrency_model: ConcurrencyModel::default(),
+            delete_order: DeleteOrder::default(),
+            blocking: false,
+            backup: None,
+            assume_empty_fast_delete: false,
+        }
+    }
+}
+
+/// Deletes the directory (or single file) at `path` and returns the resulting statistics
+///
+/// This is a convenience wrapper that builds a [`DeletionEngine`] for a single
+/// run. Individual entry failures are counted and collected in the returned
+/// stats (see [`DeletionStats::failures`]) rather than reported as an `Err`,
+/// unless `DeleteOptions::fail_fast` is set.
+pub async fn delete(path: impl AsRef<Path>, options: DeleteOptions) -> Result<DeletionStats> {
+    let engine = DeletionEngine::new(options)?;
+    let result = engine.delete_directory(path.as_ref()).await;
+    // Flushed even after a failure, whose error is logged too; a failed
+    // flush is then attached to the deletion error rather than replacing it
+    let flushed = engine.flush_log().await;
+    match (result, flushed) {
+        (Err(e), Err(flush_error)) => return Err(e.context(format!("{:#}", flush_error))),
+        (Err(e), Ok(())) | (Ok(()), Err(e)) => return Err(e),
+        (Ok(()), Ok(())) => {}
+    }
+    Arc::into_inner(engine.stats).context("Deletion statistics are still shared")
+}
+
+/// Core deletion engine that handles the recursive directory traversal and deletion
+pub struct DeletionEngine {
+    stats: Arc<DeletionStats>,
+    progress_bar: Option<ProgressBar>,
+    counted_progress: bool,
+    /// Milliseconds into the run when the throughput readout was last refreshed
+    last_rate_sample: AtomicU64,
+    progress_stream: bool,
+    on_progress: Option<ProgressCallback>,
+    interactive: bool,
+    /// Serializes `interactive` prompts
+    prompt_lock: tokio::sync::Mutex<()>,
+    /// Set once the user answers "a" to an `interactive` prompt
+    approve_all: AtomicBool,
+    /// When the engine was created; `--progress-stream` times are relative to it
+    started: Instant,
+    /// Milliseconds since `started` and files deleted at the last stream event
+    last_stream_event: AtomicU64,
+    last_stream_files: AtomicU64,
+    jobs: usize,
+    concurrency_model: ConcurrencyModel,
+    delete_order: DeleteOrder,
+    simple_max_entries: u64,
+    job_limiter: Semaphore,
+    /// Bounds concurrently open `read_dir` handles, independently of `job_limiter`
+    dir_limiter: Semaphore,
+    /// Minimum spacing between file removals under `max_rate`
+    removal_interval: Option<Duration>,
+    /// Earliest time the next rate-limited removal may start
+    next_removal: tokio::sync::Mutex<tokio::time::Instant>,
+    dry_run: bool,
+    trash: bool,
+    trash_on_error: bool,
+    excludes: GlobSet,
+    includes: GlobSet,
+    path_filter: Option<Regex>,
+    path_filter_invert: bool,
+    max_depth: Option<usize>,
+    keep_root: bool,
+    keep_file: Option<String>,
+    report_empty: bool,
+    warn_special: bool,
+    skip_special: bool,
+    on_symlink: SymlinkPolicy,
+    unlink_root_symlink: bool,
+    dereference_root: bool,
+    assume_empty_fast_delete: bool,
+    /// Thread queues shared by every target under `options.blocking`
+    blocking: Option<blocking::Pool>,
+    one_file_system: bool,
+    retries: u32,
+    force: bool,
+    /// Every target started so far; `force` leaves their parents alone
+    targets: Mutex<HashSet<PathBuf>>,
+    /// Files modified at or after this instant are kept
+    modified_before: Option<SystemTime>,
+    /// Files modified at or before this instant are kept
+    modified_after: Option<SystemTime>,
+    larger_than: Option<u64>,
+    smaller_than: Option<u64>,
+    exclude_larger_than: Option<u64>,
+    min_free: Option<u64>,
+    /// Set once `min_free` bytes have been freed; also sets `cancelled`
+    min_free_reached: AtomicBool,
+    /// Collect per top-level subdirectory totals (`stats_by_dir` or `profile`)
+    track_breakdown: bool,
+    gitignore: bool,
+    empty_dirs_only: bool,
+    verify: bool,
+    fail_fast: bool,
+    /// Options for the silent planning pass of `largest_first`, when enabled
+    plan_options: Option<DeleteOptions>,
+    /// This run's timestamped folder under `backup`; created on first use
+    backup_dir: Option<PathBuf>,
+    /// Largest files found so far; only set on a planning engine
+    plan: Option<Mutex<LargestFiles>>,
+    /// Set by [`DeletionEngine::cancel`]; no new deletions start once it is
+    cancelled: AtomicBool,
+    /// Links found so far and the link count when first found, for each
+    /// multiply-linked file, keyed by (device, inode)
+    #[cfg(unix)]
+    hardlinks: Mutex<HashMap<(u64, u64), (u64, u64)>>,
+    /// Audit log; the async mutex keeps concurrent lines from interleaving
+    log_file: Option<tokio::sync::Mutex<BufWriter<fs::File>>>,
+}
+
+/// The target a recursive deletion started from
+struct TraversalRoot<'a> {
+    path: &'a Path,
+    /// Filesystem of the root, only recorded when `one_file_system` is set
+    device: Option<u64>,
+}
+
+/// Per-directory state carried down the traversal
+#[derive(Clone, Default)]
+struct DirContext {
+    /// Level below the root (the root is 0)
+    depth: usize,
+    /// `stats_by_dir` totals of the top-level subtree being processed
+    tally: Option<Arc<SubtreeTally>>,
+    /// `.gitignore` matchers from the root down to this directory
+    gitignores: Vec<Arc<Gitignore>>,
+    /// Set once inside a gitignored directory, where everything is deleted
+    in_ignored: bool,
+    /// Set once inside a directory matching `--include`, where everything
+    /// not excluded is deleted
+    in_included: bool,
+    /// Set when a directory is listed again after a failed removal, so it
+    /// isn't reported as originally empty
+    rescan: bool,
+}
+
+impl DirContext {
+    /// Checks a path against the `.gitignore` chain, deepest file first
+    fn is_gitignored(&self, path: &Path, is_dir: bool) -> bool {
+        for matcher in self.gitignores.iter().rev() {
+            let matched = matcher.matched(path, is_dir);
+            if matched.is_ignore() {
+                return true;
+            }
+            if matched.is_whitelist() {
+                return false;
+            }
+        }
+        false
+    }
+}
+
+/// What listing one directory left for [`DeletionEngine::delete_tree`] to do
+#[derive(Default)]
+struct Listing {
+    /// Something directly inside was kept or could not be deleted
+    kept: bool,
+    /// Subdirectories to descend into, with their gitignored and included flags
+    subdirs: Vec<(PathBuf, bool, bool)>,
+    /// The `.gitignore` chain including the listed directory's own file
+    gitignores: Vec<Arc<Gitignore>>,
+    /// The directory had been replaced by a file, which was deleted (or
+    /// kept) in its place
+    replaced_by_file: bool,
+}
+
+impl Listing {
+    /// A listing that keeps the directory and queues nothing below it
+    fn kept() -> Self {
+        Self {
+            kept: true,
+            ..Self::default()
+        }
+    }
+}
+
+/// What was learned about one listed entry before classifying it
+enum EntryInfo {
+    /// Full `lstat` result
+    Metadata(std::fs::Metadata),
+    /// Only the type reported by the listing, with `assume_empty_fast_delete`
+    Type(std::fs::FileType),
+}
+
+/// What became of a file queued by [`DeletionEngine::list_directory`]
+enum ListedFile {
+    /// Whether it is gone, as returned by [`DeletionEngine::remove_file`]
+    Done(bool),
+    /// A directory has taken its place since it was listed
+    Replaced(std::fs::Metadata),
+}
+
+/// Message for a work-queue slot that was expected to hold a directory
+const LIVE_SLOT: &str = "queued directory slot is occupied";
+
+/// A directory in the work queue of [`DeletionEngine::delete_tree`]
+struct PendingDir {
+    path: PathBuf,
+    /// Slot of the parent directory; `None` for the starting directory
+    parent: Option<usize>,
+    context: DirContext,
+    /// Subdirectories that haven't finished yet
+    pending: usize,
+    /// Something below was kept or failed, so the directory must stay
+    kept: bool,
+    /// Remove the directory itself once its contents are gone
+    remove: bool,
+    /// Set once it was listed again after a "directory not empty" failure
+    rescanned: bool,
+    /// `stats_by_dir` totals and start time, for immediate children of the root
+    breakdown: Option<(Arc<SubtreeTally>, Instant)>,
+}
+
+impl PendingDir {
+    fn new(path: PathBuf, parent: Option<usize>, context: DirContext, remove: bool) -> Self {
+        Self {
+            path,
+            parent,
+            context,
+            pending: 0,
+            kept: false,
+            remove,
+            rescanned: false,
+            breakdown: None,
+        }
+    }
+}
+
+/// Loads `dir/.gitignore`, if there is one
+fn load_gitignore(dir: &Path) -> Option<Gitignore> {
+    let file = dir.join(".gitignore");
+    if !file.is_file() {
+        return None;
+    }
+
+    let mut builder = GitignoreBuilder::new(dir);
+    builder.add(file);
+    builder.build().ok()
+}
+
+impl DeletionEngine {
+    /// Creates an engine for the given options
+    ///
+    /// # Errors
+    ///
+    /// Fails if:
+    /// - `options.jobs` or `options.max_open_dirs` is zero, since a
+    ///   zero-permit semaphore would never let anything through
+    /// - `options.max_rate` is zero
+    /// - `options.newer_than` doesn't exceed `options.older_than`
+    /// - `options.keep_file` is not a plain file name or lacks `keep_root`
+    /// - an exclusion or inclusion pattern is not a valid glob, or
+    ///   `options.path_filter` not a valid regex
+    /// - `options.log_file` can't be opened or `options.progress_template`
+    ///   is invalid
+    /// - `options.backup` is combined with an option that keeps or trashes
+    ///   entries
+    /// - `options.dereference_root` is combined with
+    ///   `options.unlink_root_symlink`
+    /// - `options.assume_empty_fast_delete` is combined with an option that
+    ///   needs file metadata or keeps entries
+    /// - `options.blocking` is combined with one that needs the async engine
+    pub fn new(options: DeleteOptions) -> Result<Self> {
+        if options.jobs == 0 {
+            anyhow::bail!("--jobs must be at least 1");
+        }
+        if options.max_open_dirs == 0 {
+            anyhow::bail!("--max-open-dirs must be at least 1");
+        }
+        if options.max_rate == Some(0) {
+            anyhow::bail!("--max-rate must be at least 1");
+        }
+        if let (Some(newer_than), Some(older_than)) = (options.newer_than, options.older_than) {
+            if newer_than <= older_than {
+                anyhow::bail!("--newer-than must be longer than --older-than, or no file can match both");
+            }
+        }
+        if let Some(ref name) = options.keep_file {
+            if !options.keep_root {
+                anyhow::bail!("--keep-file requires --keep-root");
+            }
+            if Path::new(name).file_name() != Some(std::ffi::OsStr::new(name)) {
+                anyhow::bail!("--keep-file must be a plain file name, not a path: {}", name);
+            }
+        }
+        if options.dereference_root && options.unlink_root_symlink {
+            anyhow::bail!("--dereference-root-only can't be combined with --no-canonicalize, which removes a linked target as a link");
+        }
+        if options.assume_empty_fast_delete {
+            let conflicts = [
+                (options.one_file_system, "--one-file-system"),
+                (options.older_than.is_some(), "--older-than"),
+                (options.newer_than.is_some(), "--newer-than"),
+                (options.larger_than.is_some(), "--larger-than"),
+                (options.smaller_than.is_some(), "--smaller-than"),
+                (options.exclude_larger_than.is_some(), "--exclude-larger-than"),
+                (options.min_free.is_some(), "--min-free"),
+                (options.largest_first, "--largest-first"),
+                (options.warn_special, "--warn-special"),
+                (options.skip_special, "--skip-special"),
+                (options.on_symlink != SymlinkPolicy::Unlink, "--on-symlink"),
+                (!options.includes.is_empty(), "--include"),
+                (options.path_filter.is_some(), "--path-filter"),
+                (options.gitignore, "--gitignore"),
+                (options.empty_dirs_only, "--empty-dirs-only"),
+            ];
+            if let Some((_, flag)) = conflicts.iter().find(|(set, _)| *set) {
+                anyhow::bail!("--assume-empty-fast-delete skips file metadata and can't be combined with {}", flag);
+            }
+        }
+        if options.blocking {
+            let conflicts = [
+                (options.trash, "--trash"),
+                (options.trash_on_error, "--trash-on-error"),
+                (options.interactive, "--interactive"),
+                (options.gitignore, "--gitignore"),
+                (options.one_file_system, "--one-file-system"),
+                (options.warn_special, "--warn-special"),
+                (options.log_file.is_some(), "--log-file"),
+                (options.max_rate.is_some(), "--max-rate"),
+                (options.largest_first, "--largest-first"),
+                (options.stats_by_dir, "--stats-by-dir"),
+                (options.profile, "--profile"),
+                (options.verify, "--verify"),
+            ];
+            if let Some((_, flag)) = conflicts.iter().find(|(set, _)| *set) {
+                anyhow::bail!("--blocking runs without the async engine and can't be combined with {}", flag);
+            }
+        }
+
+        let excludes = build_glob_set(&options.excludes)?;
+        let includes = build_glob_set(&options.includes)?;
+        let path_filter = match &options.path_filter {
+            Some(pattern) => {
+                Some(Regex::new(pattern).with_context(|| format!("Invalid --path-filter: {}", pattern))?)
+            }
+            None => None,
+        };
+        let log_file = match &options.log_file {
+            Some(path) => {
+                let file = std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .with_context(|| format!("Failed to open log file: {}", escape_path(path)))?;
+                Some(tokio::sync::Mutex::new(BufWriter::new(fs::File::from_std(file))))
+            }
+            None => None,
+        };
+        // Checked even when no bar is shown, so a typo fails on every run
+        let custom_template = match &options.progress_template {
+            Some(template) => Some(
+                ProgressStyle::default_bar()
+                    .template(template)
+                    .with_context(|| format!("Invalid --progress-template: {}", template))?,
+            ),
+            None => None,
+        };
+        let progress_bar = if options.progress {
+            // The length is filled in by the pre-scan of each target
+            let pb = ProgressBar::new(0);
+            pb.set_style(custom_template.unwrap_or_else(|| {
+                ProgressStyle::default_bar()
+                    .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} (ETA {eta}) {prefix} {msg}")
+                    .unwrap()
+                    .progress_chars("=> ")
+            }));
+            Some(pb)
+        } else if options.verbose {
+            let pb = ProgressBar::new_spinner();
+            pb.set_style(custom_template.unwrap_or_else(|| {
+                ProgressStyle::default_spinner()
+                    .template("{spinner:.green} [{elapsed_precise}] {msg}")
+                    .unwrap()
+            }));
+            Some(pb)
+        } else {
+            None
+        };
+
+        let engine = Self {
+            stats: DeletionStats::new(),
+            progress_bar,
+            counted_progress: options.progress,
+            last_rate_sample: AtomicU64::new(0),
+            progress_stream: options.progress_stream,
+            on_progress: options.on_progress.clone(),
+            interactive: options.interactive,
+            prompt_lock: tokio::sync::Mutex::new(()),
+            approve_all: AtomicBool::new(false),
+            started: Instant::now(),
+            last_stream_event: AtomicU64::new(0),
+            last_stream_files: AtomicU64::new(0),
+            jobs: options.jobs,
+            concurrency_model: options.concurrency_model,
+            delete_order: options.delete_order,
+            simple_max_entries: options.simple_max_entries,
+            job_limiter: Semaphore::new(options.jobs),
+            dir_limiter: Semaphore::new(options.max_open_dirs),
+            removal_interval: options.max_rate.map(|rate| Duration::from_secs(1) / rate),
+            next_removal: tokio::sync::Mutex::new(tokio::time::Instant::now()),
+            dry_run: options.dry_run,
+            trash: options.trash,
+            trash_on_error: options.trash_on_error,
+            excludes,
+            includes,
+            path_filter,
+            path_filter_invert: options.path_filter_invert,
+            max_depth: options.max_depth,
+            keep_root: options.keep_root,
+            keep_file: options.keep_file.clone(),
+            report_empty: options.report_empty,
+            warn_special: options.warn_special,
+            skip_special: options.skip_special,
+            on_symlink: options.on_symlink,
+            unlink_root_symlink: options.unlink_root_symlink,
+            dereference_root: options.dereference_root,
+            assume_empty_fast_delete: options.assume_empty_fast_delete,
+            blocking: options.blocking.then(|| blocking::Pool::new(options.jobs)),
+            one_file_system: options.one_file_system,
+            retries: options.retries,
+            force: options.force,
+            targets: Mutex::new(HashSet::new()),
+            modified_before: options.older_than.and_then(|age| SystemTime::now().checked_sub(age)),
+            modified_after: options.newer_than.and_then(|age| SystemTime::now().checked_sub(age)),
+            larger_than: options.larger_than,
+            smaller_than: options.smaller_than,
+            exclude_larger_than: options.exclude_larger_than,
+            min_free: options.min_free,
+            min_free_reached: AtomicBool::new(false),
+            track_breakdown: options.stats_by_dir || options.profile,
+            gitignore: options.gitignore,
+            empty_dirs_only: options.empty_dirs_only,
+            verify: options.verify,
+            fail_fast: options.fail_fast,
+            plan_options: (options.largest_first && !options.dry_run && !options.interactive).then(|| DeleteOptions {
+                verbose: false,
+                on_progress: None,
+                dry_run: true,
+                trash: false,
+                trash_on_error: false,
+                progress: false,
+                progress_stream: false,
+                stats_by_dir: false,
+                profile: false,
+                interactive: false,
+                log_file: None,
+                min_free: None,
+                fail_fast: false,
+                verify: false,
+                largest_first: false,
+                max_rate: None,
+                backup: None,
+                ..options.clone()
+            }),
+            backup_dir: options.backup.as_ref().filter(|_| !options.dry_run).map(|base| {
+                // Colons aren't allowed in Windows file names
+                let stamp = humantime::format_rfc3339_seconds(SystemTime::now()).to_string().replace(':', "-");
+                base.join(format!("fastdel-{}", stamp))
+            }),
+            plan: None,
+            cancelled: AtomicBool::new(false),
+            #[cfg(unix)]
+            hardlinks: Mutex::new(HashMap::new()),
+            log_file,
+        };
+        if engine.backup_dir.is_some() && (engine.trash || engine.keep_root || engine.has_filters()) {
+            anyhow::bail!("--backup moves whole targets and can't be combined with --trash, --keep-root or filters");
+        }
+        Ok(engine)
+    }
+
+    /// This run's folder under `DeleteOptions::backup`, if backing up
+    pub fn backup_dir(&self) -> Option<&Path> {
+        self.backup_dir.as_deref()
+    }
+
+    /// Main entry point for directory deletion
+    /// 
+    /// This function orchestrates the entire deletion process:
+    /// 1. Validates the target path exists; a file is removed on its own
+    /// 2. Initiates recursive deletion with proper error handling
+    /// 3. Ensures the root directory is removed last, unless entries inside it
+    ///    were intentionally kept (by an exclusion or the depth limit) or
+    ///    `keep_root` asks for it to be preserved
+    pub async fn delete_directory(&self, path: &Path) -> Result<()> {
+        let span = tracing::info_span!("delete", target = %escape_path(path));
+        let started = Instant::now();
+        let result = self.delete_target(path).instrument(span).await;
+        trace!("Finished {} in {:.3}s", escape_path(path), started.elapsed().as_secs_f64());
+        if let Err(ref e) = result {
+            self.log_to_file("ERROR", &format!("{:#}", e)).await;
+        }
+        result
+    }
+
+    /// Deletes a single target; see [`DeletionEngine::delete_directory`]
+    async fn delete_target(&self, path: &Path) -> Result<()> {
+        if self.is_cancelled() {
+            return Ok(());
+        }
+
+        // Only the target itself is resolved; everything below is still
+        // looked at with `symlink_metadata`
+        let resolved = if self.dereference_root && self.on_symlink != SymlinkPolicy::Follow {
+            match fs::symlink_metadata(path).await {
+                Ok(metadata) if is_link(&metadata) => Some(
+                    fs::canonicalize(path)
+                        .await
+                        .with_context(|| format!("Failed to resolve symlink: {}", escape_path(path)))?,
+                ),
+                _ => None,
+            }
+        } else {
+            None
+        };
+        if let Some(ref resolved) = resolved {
+            trace!("Following target symlink {} to {}", escape_path(path), escape_path(resolved));
+        }
+        let path = resolved.as_deref().unwrap_or(path);
+        if let Ok(mut targets) = self.targets.lock() {
+            targets.insert(path.to_path_buf());
+        }
+
+        // A symlinked root would otherwise delete the real directory's contents
+        if self.on_symlink != SymlinkPolicy::Follow {
+            match fs::symlink_metadata(path).await {
+                Ok(metadata) if self.unlink_root_symlink && self.backup_dir.is_none() && is_link(&metadata) => {
+                    return self.delete_single_file(path, &metadata).await;
+                }
+                _ => refuse_symlink_root(path).await?,
+            }
+        }
+
+        // Validate that the path exists
+        let metadata = fs::metadata(path).await
+            .with_context(|| format!("Failed to access path: {}", escape_path(path)))?;
+
+        if let Some(ref backup_dir) = self.backup_dir {
+            return self.backup_target(path, &metadata, backup_dir).await;
+        }
+        if !metadata.is_dir() {
+            return self.delete_single_file(path, &metadata).await;
+        }
+        self.check_root_readable(path).await?;
+
+        trace!("Starting deletion of: {}", escape_path(path));
+        if let (true, Some(pb)) = (self.counted_progress, &self.progress_bar) {
+            pb.set_message(format!("Scanning {}", escape_path(path)));
+            let totals = scan_tree(path).await;
+            pb.inc_length(totals.files + totals.dirs);
+        }
+
+        if self.trash && !self.keep_root && !self.has_filters() {
+            return self.trash_directory(path).await;
+        }
+
+        info!("Using {} concurrent jobs", self.jobs);
+
+        let root = TraversalRoot {
+            path,
+            device: if self.one_file_system {
+                device_id(path, &metadata).await
+            } else {
+                None
+            },
+        };
+
+        if self.plan_options.is_some() {
+            self.delete_largest_files(path).await?;
+        }
+
+        // Recursively delete all contents first using concurrent deletion,
+        // then remove the emptied root directory itself
+        if self.keep_root {
+            let kept = self.delete_tree(&root, path, false).await?;
+            trace!("Keeping root directory: {}", escape_path(path));
+            if !kept {
+                self.create_placeholder(path).await?;
+            }
+        } else if let Some(reason) = unremovable_root(path, &metadata).await {
+            // Empty it like --keep-root, but say why the root itself stays
+            if !self.delete_tree(&root, path, false).await? {
+                self.stats.record_kept_root(path, reason);
+                let message = format!(
+                    "Emptied but kept {}: it is {} and can't be removed (use --keep-root to skip this notice)",
+                    escape_path(path),
+                    reason
+                );
+                warn!("{}", message);
+                self.log_to_file("WARN", &message).await;
+            }
+        } else if self.delete_small_tree(path).await? {
+            trace!("Removed {} with a single remove_dir_all", escape_path(path));
+        } else if self.delete_tree(&root, path, true).await? {
+            trace!(
+                "Keeping {} because it still contains kept or undeletable entries",
+                escape_path(path)
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Finishes an interrupted run from the files listed in a `--manifest`
+    ///
+    /// Each listed path is looked up without following links. Files that are
+    /// already gone are only counted; the rest are deleted like single-file
+    /// targets, `jobs` at a time, with the usual filters, retries and stats.
+    /// A manifest lists no directories, so emptied ones are left in place.
+    /// Returns the number of listed files that were already gone.
+    pub async fn resume_from_manifest(&self, manifest: &Path) -> Result<u64> {
+        let text = fs::read_to_string(manifest)
+            .await
+            .with_context(|| format!("Failed to read manifest: {}", escape_path(manifest)))?;
+        let mut listed = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry: ListedPath = serde_json::from_str(line)
+                .with_context(|| format!("Invalid manifest line {} in {}", index + 1, escape_path(manifest)))?;
+            listed.push(entry.path);
+        }
+        trace!("Resuming {} listed files from {}", listed.len(), escape_path(manifest));
+
+        let mut already_gone = 0;
+        let mut deletions = stream::iter(listed)
+            .map(|text| self.resume_entry(text))
+            .buffer_unordered(self.jobs);
+        while let Some(gone) = deletions.next().await {
+            already_gone += u64::from(gone?);
+        }
+        Ok(already_gone)
+    }
+
+    /// Deletes one manifest entry if it still exists, returning whether it was gone
+    async fn resume_entry(&self, text: String) -> Result<bool> {
+        if self.is_cancelled() {
+            return Ok(false);
+        }
+
+        let path = PathBuf::from(&text);
+        let found = match fs::symlink_metadata(&path).await {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => match unescape_path(&text) {
+                Some(raw) => fs::symlink_metadata(&raw).await.map(|metadata| (raw, metadata)),
+                None => Err(e),
+            },
+            found => found.map(|metadata| (path.clone(), metadata)),
+        };
+        match found {
+            Ok((path, metadata)) if metadata.is_dir() && !is_link(&metadata) => {
+                // Something else took the name since; the manifest only vouches for files
+                self.stats.increment_skipped();
+                trace!("Skipping listed file that is now a directory: {}", escape_path(&path));
+                Ok(false)
+            }
+            Ok((path, metadata)) => {
+                self.delete_single_file(&path, &metadata).await?;
+                Ok(false)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                trace!("Already gone: {}", escape_path(&path));
+                Ok(true)
+            }
+            Err(e) => {
+                self.record_error("Failed to get metadata for", &path, &e).await?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Deletes the largest files under `path` ahead of the regular traversal
+    ///
+    /// A planning pass (a silent dry run with the same filters) keeps the
+    /// `LARGEST_FIRST_MAX_FILES` largest candidates, which are then deleted in
+    /// descending size order, `jobs` at a time. Everything else, including
+    /// files that failed here, is left to the traversal that follows, which
+    /// also reports the errors.
+    async fn delete_largest_files(&self, path: &Path) -> Result<()> {
+        let Some(ref options) = self.plan_options else {
+            return Ok(());
+        };
+        let mut planner = DeletionEngine::new(options.clone())?;
+        planner.plan = Some(Mutex::new(BinaryHeap::new()));
+        // The planning pass would repeat every skip message of the real one
+        Box::pin(planner.delete_target(path))
+            .with_subscriber(NoSubscriber::default())
+            .await?;
+        let plan = planner.plan.take().map_or_else(BinaryHeap::new, |plan| plan.into_inner().unwrap());
+
+        // Sorting `Reverse` entries ascending puts the largest file first
+        let files = plan.into_sorted_vec();
+        trace!("Deleting the {} largest files first", files.len());
+        let removals = files.into_iter().map(|Reverse((size, file))| async move {
+            if let Err(e) = self.attempt_file_removal(&file, size, None).await? {
+                trace!("Couldn't delete {} early ({}), retrying later", escape_path(&file), e);
+            }
+            Ok::<(), anyhow::Error>(())
+        });
+        let mut results = stream::iter(removals).buffer_unordered(self.file_jobs());
+        while let Some(result) = results.next().await {
+            result?;
+        }
+        Ok(())
+    }
+
+    /// Records and reports a FIFO, socket or device node kept by `skip_special`
+    ///
+    /// Returns whether the entry must be left in place.
+    fn keeps_special(&self, path: &Path, metadata: &std::fs::Metadata) -> bool {
+        let Some(kind) = special_kind(metadata).filter(|_| self.skip_special) else {
+            return false;
+        };
+        self.stats.record_special_kept(path);
+        trace!("Skipping {}: {}", kind, escape_path(path));
+        true
+    }
+
+    /// Checks a listed file against everything that can keep it, recording
+    /// and reporting why it stays
+    ///
+    /// `unmatched_by_gitignore` is only asked once the cheaper checks before
+    /// it have passed.
+    fn keeps_listed_file(
+        &self,
+        root: &Path,
+        path: &Path,
+        metadata: &std::fs::Metadata,
+        filter_included: bool,
+        unmatched_by_gitignore: impl FnOnce() -> bool,
+    ) -> bool {
+        if self.empty_dirs_only || self.keeps_special(path, metadata) {
+            return true;
+        }
+        let reason = if unmatched_by_gitignore() {
+            "Skipping file not matched by .gitignore"
+        } else if filter_included && !self.is_included(root, path) {
+            "Skipping file not matched by --include"
+        } else if self.is_path_filtered(path) {
+            "Skipping file kept by --path-filter"
+        } else if self.is_filtered_out(metadata) {
+            "Skipping filtered file"
+        } else if self.is_protected_size(metadata) {
+            "Keeping file larger than --exclude-larger-than"
+        } else {
+            return false;
+        };
+        self.stats.increment_skipped();
+        trace!("{}: {}", reason, escape_path(path));
+        true
+    }
+
+    /// Warns about and records a file with extended attributes or ACLs
+    ///
+    /// Files whose attributes can't be listed are not flagged.
+    async fn check_special(&self, path: &Path) {
+        let target = path.to_path_buf();
+        let special = tokio::task::spawn_blocking(move || has_special_xattrs(&target))
+            .await
+            .is_ok_and(|result| result.unwrap_or(false));
+        if special {
+            self.stats.record_special_file(path);
+            let message = format!("Has extended attributes or ACLs: {}", escape_path(path));
+            warn!("{}", message);
+            self.log_to_file("WARN", &message).await;
+        }
+    }
+
+    /// Creates the `keep_file` placeholder in an emptied root, if configured
+    async fn create_placeholder(&self, root: &Path) -> Result<()> {
+        let (Some(name), false) = (&self.keep_file, self.dry_run) else {
+            return Ok(());
+        };
+        let placeholder = root.join(name);
+        fs::File::create(long_path(&placeholder))
+            .await
+            .with_context(|| format!("Failed to create placeholder: {}", escape_path(&placeholder)))?;
+        self.stats.increment_placeholders();
+        let message = format!("Created placeholder: {}", escape_path(&placeholder));
+        trace!("{}", message);
+        self.log_to_file("INFO", &message).await;
+        Ok(())
+    }
+
+    /// Fails with one clear error if the root directory can't be listed
+    ///
+    /// Otherwise the traversal would record an unreadable root and then a
+    /// failed removal for the same cause. With `force` on Unix, the owner's
+    /// read, write and search bits are restored on the root first.
+    async fn check_root_readable(&self, path: &Path) -> Result<()> {
+        let error = match fs::read_dir(long_path(path)).await {
+            Ok(_) => return Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => e,
+            // Anything else is reported per entry by the traversal as usual
+            Err(_) => return Ok(()),
+        };
+
+        #[cfg(unix)]
+        if self.force && make_listable(path).await.is_ok() && fs::read_dir(path).await.is_ok() {
+            trace!("Restored owner permissions on: {}", escape_path(path));
+            return Ok(());
+        }
+
+        let hint = if cfg!(unix) && !self.force {
+            "use --force to restore the owner's permissions, or run as a user allowed to read it"
+        } else {
+            "run as a user allowed to read it (e.g. an elevated or administrator shell)"
+        };
+        Err(error).with_context(|| format!("Permission denied reading {}; {}", escape_path(path), hint))
+    }
+
+    /// Deletes a target that is a file rather than a directory, like `rm`
+    ///
+    /// The include, exclude, size and age filters still apply;
+    /// `empty_dirs_only` keeps it.
+    async fn delete_single_file(&self, path: &Path, metadata: &std::fs::Metadata) -> Result<()> {
+        trace!("Starting deletion of file: {}", escape_path(path));
+        if let (true, Some(pb)) = (self.counted_progress, &self.progress_bar) {
+            pb.inc_length(1);
+        }
+
+        let not_included = !self.includes.is_empty() && !self.is_included(path, path);
+        let filtered = self.is_filtered_out(metadata) || self.is_path_filtered(path);
+        if self.empty_dirs_only || not_included || self.is_excluded(path, path) || filtered {
+            self.stats.increment_skipped();
+            trace!("Skipping filtered file: {}", escape_path(path));
+            return Ok(());
+        }
+        if self.is_protected_size(metadata) {
+            self.stats.increment_skipped();
+            trace!("Keeping file larger than --exclude-larger-than: {}", escape_path(path));
+            return Ok(());
+        }
+        if self.keeps_special(path, metadata) {
+            return Ok(());
+        }
+
+        if self.warn_special {
+            self.check_special(path).await;
+        }
+        self.remove_file(path, self.freed_size(metadata), None).await?;
+        Ok(())
+    }
+
+    /// Moves a whole directory tree to the OS trash in a single operation
+    ///
+    /// Trashing is atomic from our point of view, so the statistics come from a
+    /// pre-scan and are only recorded once the move has succeeded.
+    async fn trash_directory(&self, path: &Path) -> Result<()> {
+        let totals = scan_tree(path).await;
+
+        let target = path.to_path_buf();
+        tokio::task::spawn_blocking(move || trash::delete(&target))
+            .await?
+            .with_context(|| {
+                format!(
+                    "Failed to move {} to trash (is trash supported on this filesystem?)",
+                    escape_path(path)
+                )
+            })?;
+
+        self.stats.add_scan(totals);
+        let message = format!("Moved to trash: {}", escape_path(path));
+        self.record_progress(ProgressEvent::tree(path, totals), &message).await;
+        self.verify_removed(path).await;
+        Ok(())
+    }
+
+    /// Removes a small target with one blocking `remove_dir_all`
+    ///
+    /// Returns `false`, leaving the target to the traversal, if the options
+    /// need to see individual entries, if the target has more than
+    /// `simple_max_entries` entries or can't be fully scanned, or if the call
+    /// fails. In the last case the entries it did remove are counted first,
+    /// so the traversal only retries and reports what's left.
+    async fn delete_small_tree(&self, path: &Path) -> Result<bool> {
+        if !self.allows_simple_removal() {
+            return Ok(false);
+        }
+        let Some(totals) = scan_small_tree(path, self.simple_max_entries).await else {
+            return Ok(false);
+        };
+
+        let target = long_path(path).into_owned();
+        let in_flight = self.stats.begin_removal();
+        let result = tokio::task::spawn_blocking(move || std::fs::remove_dir_all(target)).await?;
+        drop(in_flight);
+        if let Err(e) = result {
+            trace!("remove_dir_all failed on {} ({}), deleting entry by entry", escape_path(path), e);
+            let left = scan_tree(path).await;
+            self.stats.add_scan(ScanTotals {
+                files: totals.files.saturating_sub(left.files),
+                dirs: totals.dirs.saturating_sub(left.dirs),
+                bytes: totals.bytes.saturating_sub(left.bytes),
+            });
+            return Ok(false);
+        }
+
+        self.stats.add_scan(totals);
+        let message = format!("Deleted small tree: {}", escape_path(path));
+        self.record_progress(ProgressEvent::tree(path, totals), &message).await;
+        Ok(true)
+    }
+
+    /// Returns whether nothing in the options needs the entry-by-entry traversal
+    fn allows_simple_removal(&self) -> bool {
+        self.simple_max_entries > 0
+            && !self.has_filters()
+            && !self.dry_run
+            && !self.verify
+            && !self.report_empty
+            && !self.warn_special
+            && !self.track_breakdown
+            && self.on_symlink == SymlinkPolicy::Unlink
+            && self.removal_interval.is_none()
+            && self.plan_options.is_none()
+            && self.on_progress.is_none()
+            && self.log_file.is_none()
+            // The bounded pre-scan stats every entry, which this mode avoids
+            && !self.assume_empty_fast_delete
+    }
+
+    /// Moves a whole target into `backup_dir`
+    ///
+    /// A rename is tried first. If the backup folder is on another filesystem
+    /// the target is copied there instead and then deleted by the regular
+    /// traversal; a copy that fails midway is removed again, leaving the
+    /// target untouched.
+    async fn backup_target(&self, path: &Path, metadata: &std::fs::Metadata, backup_dir: &Path) -> Result<()> {
+        fs::create_dir_all(backup_dir)
+            .await
+            .with_context(|| format!("Failed to create backup directory: {}", escape_path(backup_dir)))?;
+        let backup_dir = fs::canonicalize(backup_dir).await?;
+        if backup_dir.starts_with(fs::canonicalize(path).await?) {
+            anyhow::bail!("Refusing to back up {} into itself ({})", escape_path(path), escape_path(&backup_dir));
+        }
+        let destination = unique_destination(&backup_dir, path).await;
+
+        let totals = scan_tree(path).await;
+        if let (true, Some(pb)) = (self.counted_progress, &self.progress_bar) {
+            pb.inc_length(totals.files + totals.dirs);
+        }
+
+        match fs::rename(path, &destination).await {
+            Ok(()) => {
+                self.stats.add_scan(totals);
+                self.stats.increment_backup_renames();
+                let message = format!("Moved to backup: {} -> {}", escape_path(path), escape_path(&destination));
+                self.record_progress(ProgressEvent::tree(path, totals), &message).await;
+                self.verify_removed(path).await;
+                return Ok(());
+            }
+            Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+                trace!("{} is on another filesystem, copying {}", escape_path(&backup_dir), escape_path(path));
+            }
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to move {} to {}", escape_path(path), escape_path(&destination)));
+            }
+        }
+
+        let (source, target) = (path.to_path_buf(), destination.clone());
+        let copied = tokio::task::spawn_blocking(move || copy_tree(&source, &target)).await?;
+        if let Err(e) = copied {
+            let _ = fs::remove_dir_all(&destination).await;
+            let _ = fs::remove_file(&destination).await;
+            return Err(e).with_context(|| format!("Failed to copy {} to {}", escape_path(path), escape_path(&destination)));
+        }
+        self.stats.increment_backup_copies();
+        let message = format!("Copied to backup: {} -> {}", escape_path(path), escape_path(&destination));
+        trace!("{}", message);
+        self.log_to_file("INFO", &message).await;
+
+        if metadata.is_dir() {
+            let root = TraversalRoot { path, device: None };
+            self.delete_tree(&root, path, true).await?;
+        } else {
+            self.remove_file(path, self.freed_size(metadata), None).await?;
+        }
+        Ok(())
+    }
+
+    /// Lists one directory, deleting its files and collecting its subdirectories
+    ///
+    /// This is the per-directory step of [`DeletionEngine::delete_tree`]:
+    /// - Reads the metadata of up to `jobs` entries at once while listing, in order
+    /// - Deletes the files concurrently as the listing is read
+    /// - Returns the subdirectories for the caller to queue, instead of
+    ///   descending into them itself
+    ///
+    /// Symlinks are classified with `symlink_metadata` and unlinked like files,
+    /// so a link pointing outside the tree never causes its target to be
+    /// traversed. On Windows, junctions and other reparse points are treated
+    /// the same way. [`SymlinkPolicy::Skip`] keeps them instead, and
+    /// [`SymlinkPolicy::Follow`] restores the old following behavior.
+    ///
+    /// `context.tally` collects the `stats_by_dir` totals of the top-level
+    /// subtree being processed; it is `None` for the root's own files.
+    ///
+    /// With `gitignore`, only entries matched by the `.gitignore` files seen so
+    /// far are deleted. A matched directory is deleted as a whole; unmatched
+    /// directories are searched for matches, and `.git` is never entered.
+    ///
+    /// With `includes`, only files matching a pattern are deleted, while a
+    /// matching directory is deleted as a whole. Other directories are removed
+    /// only if emptied; ones that were already empty are kept.
+    ///
+    /// With `empty_dirs_only`, every non-directory is kept, so only directories
+    /// that end up with no entries on the way back up are removed.
+    ///
+    /// `context.depth` is the level of `dir_path` below the root (the root is 0).
+    /// Once it reaches `max_depth`, subdirectories are left untouched. With
+    /// `one_file_system`, subdirectories on a different device than the root
+    /// are never entered.
+    ///
+    /// [`Listing::kept`] is set if anything directly inside `dir_path` was
+    /// intentionally kept (for example by an `--exclude` pattern) or could not
+    /// be deleted, in which case `dir_path` itself must not be removed. Only
+    /// the entry that actually failed is counted as an error, not every
+    /// ancestor.
+    async fn list_directory(
+        &self,
+        root: &TraversalRoot<'_>,
+        dir_path: &Path,
+        mut context: DirContext,
+    ) -> Result<Listing> {
+        // After cancellation everything not yet removed counts as kept, so no
+        // parent directory is attempted
+        if self.is_cancelled() {
+            return Ok(Listing::kept());
+        }
+
+        let depth = context.depth;
+        let tally = context.tally.as_deref();
+        let filter_gitignored = self.gitignore && !context.in_ignored;
+        let filter_included = !self.includes.is_empty() && !context.in_included;
+        if filter_gitignored {
+            if let Some(matcher) = load_gitignore(dir_path) {
+                context.gitignores.push(Arc::new(matcher));
+            }
+        }
+
+        // Read directory entries, holding a permit until the handle is closed
+        let dir_permit = self.dir_limiter.acquire().await?;
+        self.stats.increment_fs_calls();
+        let entries = match fs::read_dir(long_path(dir_path)).await {
+            Ok(entries) => entries,
+            // Without filters there is nothing to check the file against first
+            Err(e) if e.kind() == io::ErrorKind::NotADirectory && depth > 0 && !self.has_filters() => {
+                drop(dir_permit);
+                let removed = self.remove_replaced_directory(dir_path, tally).await?;
+                return Ok(Listing {
+                    kept: !removed,
+                    replaced_by_file: true,
+                    ..Listing::default()
+                });
+            }
+            Err(e) => {
+                self.record_error("Failed to read directory", dir_path, &e).await?;
+                return Ok(Listing::kept()); // Can't be emptied, but continue with other operations
+            }
+        };
+
+        // Separate files and directories. Files are deleted while the listing
+        // is still being read, so a huge directory shows progress right away
+        // and never holds all of its entries in memory; the bounded queue
+        // keeps the listing from running far ahead of the deletions
+        let (mut file_queue, queued_files) = mpsc::channel::<(PathBuf, u64)>(self.jobs);
+        let scan = async {
+            let mut dir_paths = Vec::new();
+            let mut kept = false;
+            let mut found_files = false;
+            let mut listed = false;
+            let listing = stream::unfold(entries, |mut entries| async move {
+                match entries.next_entry().await {
+                    Ok(Some(entry)) => Some((entry, entries)),
+                    _ => None,
+                }
+            });
+            // Up to `jobs` entries are stat'ed at once, which hides the latency
+            // of network filesystems; `buffered` still yields them in listing order
+            let mut classified = Box::pin(listing
+                .map(|entry| async move {
+                    // Joined onto the unprefixed path so exclusion and log output see the original form
+                    let path = dir_path.join(entry.file_name());
+                    if self.is_excluded(root.path, &path) {
+                        return (path, None);
+                    }
+                    if self.assume_empty_fast_delete {
+                        // The type usually comes with the listing itself, so no stat is issued
+                        let file_type = entry.file_type().await.map(EntryInfo::Type);
+                        return (path, Some(file_type));
+                    }
+                    let metadata = self.entry_metadata(&path).await.map(EntryInfo::Metadata);
+                    (path, Some(metadata))
+                })
+                .buffered(self.jobs));
+            while let Some((path, metadata)) = classified.next().await {
+                listed = true;
+                let Some(metadata) = metadata else {
+                    kept = true;
+                    self.stats.increment_skipped();
+                    trace!("Skipping excluded entry: {}", escape_path(&path));
+                    continue;
+                };
+
+                match metadata {
+                    Ok(EntryInfo::Type(file_type)) => {
+                        if file_type.is_dir() {
+                            dir_paths.push((path, false, false));
+                        } else {
+                            found_files = true;
+                            if file_queue.send((path, 0)).await.is_err() {
+                                break;
+                            }
+                        }
+                    }
+                    Ok(EntryInfo::Metadata(metadata)) if self.on_symlink == SymlinkPolicy::Skip && is_link(&metadata) => {
+                        kept = true;
+                        self.stats.increment_symlinks_skipped();
+                        trace!("Skipping symlink: {}", escape_path(&path));
+                    }
+                    Ok(EntryInfo::Metadata(metadata)) => {
+                        if metadata.is_dir() && !is_link(&metadata) {
+                            if self.crosses_filesystem(root, &path, &metadata).await {
+                                kept = true;
+                                self.stats.increment_mounts_skipped();
+                                trace!("Skipping directory on another filesystem: {}", escape_path(&path));
+                                continue;
+                            }
+                            let ignored = filter_gitignored && context.is_gitignored(&path, true);
+                            if filter_gitignored && !ignored && path.file_name().is_some_and(|name| name == ".git") {
+                                kept = true;
+                                self.stats.increment_skipped();
+                                trace!("Skipping git directory: {}", escape_path(&path));
+                                continue;
+                            }
+                            let included = filter_included && self.is_included(root.path, &path);
+                            dir_paths.push((path, ignored, included));
+                        } else {
+                            let unmatched_by_gitignore = || filter_gitignored && !context.is_gitignored(&path, false);
+                            if self.keeps_listed_file(root.path, &path, &metadata, filter_included, unmatched_by_gitignore) {
+                                kept = true;
+                                continue;
+                            }
+                            if self.warn_special {
+                                self.check_special(&path).await;
+                            }
+                            let size = self.freed_size(&metadata);
+                            found_files = true;
+                            if file_queue.send((path, size)).await.is_err() {
+                                break;
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        kept = true;
+                        self.record_error("Failed to get metadata for", &path, &e).await?;
+                    }
+                }
+            }
+            drop(file_queue);
+            drop(classified);
+            drop(dir_permit);
+            Ok::<_, anyhow::Error>((dir_paths, kept, found_files, listed))
+        };
+        let file_deletions = queued_files
+            .map(|(file_path, size)| async move {
+                let outcome = self.remove_listed_file(&file_path, size, tally).await?;
+                Ok::<_, anyhow::Error>((file_path, outcome))
+            })
+            .buffer_unordered(self.file_jobs())
+            .fold(Ok((false, Vec::new())), |state: Result<(bool, Vec<_>)>, result| async move {
+                let (mut files_kept, mut replaced) = state?;
+                match result? {
+                    (_, ListedFile::Done(removed)) => files_kept |= !removed,
+                    (path, ListedFile::Replaced(metadata)) => replaced.push((path, metadata)),
+                }
+                Ok((files_kept, replaced))
+            });
+        let (scanned, deleted) = futures::join!(scan, file_deletions);
+        let (mut dir_paths, mut kept, found_files, listed) = scanned?;
+        let (files_kept, replaced) = deleted?;
+        kept |= files_kept;
+        // The file passed every filter, so the directory in its place is
+        // descended into as if it had matched them as a whole
+        for (path, metadata) in replaced {
+            if self.crosses_filesystem(root, &path, &metadata).await {
+                kept = true;
+                self.stats.increment_mounts_skipped();
+                trace!("Skipping directory on another filesystem: {}", escape_path(&path));
+                continue;
+            }
+            dir_paths.push((path, filter_gitignored, filter_included));
+        }
+        if self.report_empty && !listed && !context.rescan {
+            self.stats.record_empty_dir(dir_path);
+        }
+
+        // With --include, only directories emptied by this run are removed
+        if filter_included && !kept && !found_files && dir_paths.is_empty() {
+            trace!("Keeping empty directory not matched by --include: {}", escape_path(dir_path));
+            return Ok(Listing::kept());
+        }
+
+        // Stop descending once the depth limit is reached
+        if self.max_depth.is_some_and(|max_depth| depth >= max_depth) {
+            for (dir_path, _, _) in &dir_paths {
+                self.stats.increment_skipped();
+                trace!("Skipping directory beyond max depth: {}", escape_path(dir_path));
+            }
+            return Ok(Listing {
+                kept: kept || !dir_paths.is_empty(),
+                ..Listing::default()
+            });
+        }
+
+        Ok(Listing {
+            kept,
+            subdirs: dir_paths,
+            gitignores: context.gitignores,
+            replaced_by_file: false,
+        })
+    }
+
+    /// Returns how many bytes of disk space removing this file will actually free
+    ///
+    /// See [`linked_usage`]; links are stat'ed just before they are removed,
+    /// which is why it compares against the link count of the first one.
+    #[cfg(unix)]
+    fn freed_size(&self, metadata: &std::fs::Metadata) -> u64 {
+        linked_usage(&mut self.hardlinks.lock().unwrap(), metadata)
+    }
+
+    #[cfg(not(unix))]
+    fn freed_size(&self, metadata: &std::fs::Metadata) -> u64 {
+        disk_usage(metadata)
+    }
+
+    /// Reads the metadata used to classify an entry as a file or directory
+    async fn entry_metadata(&self, path: &Path) -> io::Result<std::fs::Metadata> {
+        self.stats.increment_fs_calls();
+        let path = long_path(path);
+        if self.on_symlink == SymlinkPolicy::Follow {
+            fs::metadata(&path).await
+        } else {
+            fs::symlink_metadata(&path).await
+        }
+    }
+
+    /// Checks whether a subdirectory lives on a different filesystem than the root
+    ///
+    /// Always `false` unless `one_file_system` is set and both devices are known.
+    async fn crosses_filesystem(&self, root: &TraversalRoot<'_>, path: &Path, metadata: &std::fs::Metadata) -> bool {
+        let Some(root_device) = root.device else {
+            return false;
+        };
+        device_id(path, metadata).await.is_some_and(|device| device != root_device)
+    }
+
+    /// How many files of one directory are removed at once
+    fn file_jobs(&self) -> usize {
+        match self.concurrency_model {
+            ConcurrencyModel::Dirs => 1,
+            ConcurrencyModel::Files | ConcurrencyModel::Both => self.jobs,
+        }
+    }
+
+    /// How many directories are listed at once
+    fn dir_jobs(&self) -> usize {
+        match self.concurrency_model {
+            ConcurrencyModel::Files => 1,
+            ConcurrencyModel::Dirs | ConcurrencyModel::Both => self.jobs,
+        }
+    }
+
+    /// Returns whether any option may cause entries to be kept
+    fn has_filters(&self) -> bool {
+        !self.excludes.is_empty()
+            || !self.includes.is_empty()
+            || self.path_filter.is_some()
+            || self.max_depth.is_some()
+            || self.one_file_system
+            || self.modified_before.is_some()
+            || self.modified_after.is_some()
+            || self.larger_than.is_some()
+            || self.smaller_than.is_some()
+            || self.exclude_larger_than.is_some()
+            || self.min_free.is_some()
+            || self.on_symlink == SymlinkPolicy::Skip
+            || self.gitignore
+            || self.empty_dirs_only
+            || self.skip_special
+            || self.interactive
+    }
+
+    /// Returns whether a file is big enough to be kept by `exclude_larger_than`
+    fn is_protected_size(&self, metadata: &std::fs::Metadata) -> bool {
+        self.exclude_larger_than.is_some_and(|max| metadata.len() > max)
+    }
+
+    /// Checks a file's metadata against the age and size filters
+    ///
+    /// Files whose modification time can't be read are kept, since we can't
+    /// prove they are in the age window.
+    fn is_filtered_out(&self, metadata: &std::fs::Metadata) -> bool {
+        let size = metadata.len();
+        if self.larger_than.is_some_and(|min| size <= min) {
+            return true;
+        }
+        if self.smaller_than.is_some_and(|max| size >= max) {
+            return true;
+        }
+
+        if self.modified_before.is_some() || self.modified_after.is_some() {
+            let Ok(modified) = metadata.modified() else {
+                return true;
+            };
+            if self.modified_before.is_some_and(|cutoff| modified >= cutoff) {
+                return true;
+            }
+            if self.modified_after.is_some_and(|cutoff| modified <= cutoff) {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Asks whether to delete an immediate subdirectory of the target
+    ///
+    /// Prompts are shown one at a time, and while one is waiting for input
+    /// every job permit is held so concurrent deletions pause instead of
+    /// drawing over it. Answering "a" approves this and all later
+    /// directories; end of input counts as "no".
+    async fn confirm_subdirectory(&self, dir_path: &Path) -> Result<bool> {
+        if self.approve_all.load(Ordering::Relaxed) {
+            return Ok(true);
+        }
+
+        let totals = scan_tree(dir_path).await;
+        let _prompt = self.prompt_lock.lock().await;
+        if self.approve_all.load(Ordering::Relaxed) {
+            return Ok(true);
+        }
+        let _paused = self.job_limiter.acquire_many(self.jobs as u32).await?;
+
+        let question = format!(
+            "Delete {} ({} files, {})? [y/N/a] ",
+            escape_path(dir_path),
+            totals.files,
+            HumanBytes(totals.bytes)
+        );
+        let progress_bar = self.progress_bar.clone();
+        let answer = tokio::task::spawn_blocking(move || {
+            let ask = || -> io::Result<String> {
+                let mut stderr = io::stderr();
+                io::Write::write_all(&mut stderr, question.as_bytes())?;
+                io::Write::flush(&mut stderr)?;
+                let mut input = String::new();
+                io::stdin().read_line(&mut input)?;
+                Ok(input.trim().to_lowercase())
+            };
+            match progress_bar {
+                Some(pb) => pb.suspend(ask),
+                None => ask(),
+            }
+        })
+        .await??;
+
+        match answer.as_str() {
+            "y" | "yes" => Ok(true),
+            "a" | "all" => {
+                self.approve_all.store(true, Ordering::Relaxed);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Checks an entry against the `--exclude` patterns
+    ///
+    /// Patterns are matched against both the entry's path relative to the root
+    /// and its bare file name, so `dist/*.map` and `.cache` both work.
+    fn is_excluded(&self, root: &Path, path: &Path) -> bool {
+        matches_glob_set(&self.excludes, root, path)
+    }
+
+    /// Checks an entry against the `--include` patterns, matched like excludes
+    fn is_included(&self, root: &Path, path: &Path) -> bool {
+        matches_glob_set(&self.includes, root, path)
+    }
+
+    /// Returns whether `--path-filter` keeps a file, honoring `--path-filter-invert`
+    fn is_path_filtered(&self, path: &Path) -> bool {
+        self.path_filter
+            .as_ref()
+            .is_some_and(|filter| filter.is_match(&path.to_string_lossy()) == self.path_filter_invert)
+    }
+
+    /// Removes a single file and updates statistics
+    ///
+    /// In dry-run mode the statistics are updated as if the file had been
+    /// removed, but no syscall is issued. Returns whether the file is gone.
+    async fn remove_file(&self, file_path: &Path, size: u64, tally: Option<&SubtreeTally>) -> Result<bool> {
+        if let Some(ref plan) = self.plan {
+            let mut plan = plan.lock().unwrap();
+            plan.push(Reverse((size, file_path.to_path_buf())));
+            if plan.len() > LARGEST_FIRST_MAX_FILES {
+                plan.pop(); // Drops the smallest
+            }
+            return Ok(true);
+        }
+        if self.dry_run {
+            self.stats.increment_files();
+            self.stats.add_bytes(size);
+            self.check_min_free();
+            if let Some(tally) = tally {
+                tally.add_file(size);
+            }
+            let message = format!("Would delete file: {}", escape_path(file_path));
+            self.record_progress(ProgressEvent::file(file_path, size), &message).await;
+            return Ok(true);
+        }
+
+        match self.attempt_file_removal(file_path, size, tally).await? {
+            Ok(removed) => Ok(removed),
+            Err(e) => {
+                self.record_error("Failed to delete file", file_path, &e).await?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Removes a file found by [`DeletionEngine::list_directory`]
+    ///
+    /// Like [`DeletionEngine::remove_file`], except that if the removal fails
+    /// because a directory has taken the file's place since it was listed,
+    /// that directory is handed back to be deleted as a subdirectory instead
+    /// of counting an error.
+    async fn remove_listed_file(&self, file_path: &Path, size: u64, tally: Option<&SubtreeTally>) -> Result<ListedFile> {
+        if self.dry_run || self.plan.is_some() {
+            return Ok(ListedFile::Done(self.remove_file(file_path, size, tally).await?));
+        }
+
+        match self.attempt_file_removal(file_path, size, tally).await? {
+            Ok(removed) => Ok(ListedFile::Done(removed)),
+            Err(e) => match self.entry_metadata(file_path).await {
+                Ok(metadata) if metadata.is_dir() && !is_link(&metadata) => {
+                    trace!("File was replaced by a directory, deleting it as one: {}", escape_path(file_path));
+                    Ok(ListedFile::Replaced(metadata))
+                }
+                _ => {
+                    self.record_error("Failed to delete file", file_path, &e).await?;
+                    Ok(ListedFile::Done(false))
+                }
+            },
+        }
+    }
+
+    /// Removes a file that has taken the place of a queued directory
+    ///
+    /// Only used without filters, since the file was never checked against
+    /// them. Returns whether it is gone.
+    async fn remove_replaced_directory(&self, path: &Path, tally: Option<&SubtreeTally>) -> Result<bool> {
+        trace!("Directory was replaced by a file, deleting it as one: {}", escape_path(path));
+        match self.entry_metadata(path).await {
+            Ok(metadata) => self.remove_file(path, self.freed_size(&metadata), tally).await,
+            Err(e) => {
+                self.record_error("Failed to get metadata for", path, &e).await?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Removes a file with retries and the `trash_on_error` fallback, and
+    /// records it if it succeeded
+    ///
+    /// A failure is returned rather than recorded, so the caller decides
+    /// whether it counts as an error. `Ok(false)` means the run was cancelled
+    /// before the file was attempted.
+    async fn attempt_file_removal(
+        &self,
+        file_path: &Path,
+        size: u64,
+        tally: Option<&SubtreeTally>,
+    ) -> Result<io::Result<bool>> {
+        let _permit = self.job_limiter.acquire().await?;
+        if self.is_cancelled() {
+            return Ok(Ok(false)); // Queued behind the limiter when the run was cancelled
+        }
+        self.throttle().await;
+        let result = self
+            .with_retries(file_path, || async {
+                if self.trash {
+                    move_to_trash(file_path).await
+                } else {
+                    remove_file_or_link(file_path).await
+                }
+            })
+            .await;
+        match result {
+            Ok(()) => {
+                self.stats.increment_files();
+                self.stats.add_bytes(size);
+                self.check_min_free();
+                if let Some(tally) = tally {
+                    tally.add_file(size);
+                }
+                let message = format!("Deleted file: {}", escape_path(file_path));
+                self.record_progress(ProgressEvent::file(file_path, size), &message).await;
+                self.verify_removed(file_path).await;
+                Ok(Ok(true))
+            }
+            Err(e) if self.trash_on_error && !self.trash && move_to_trash(file_path).await.is_ok() => {
+                self.stats.increment_files();
+                self.stats.increment_trash_fallbacks();
+                if let Some(tally) = tally {
+                    tally.add_file(size);
+                }
+                let message = format!("Moved to trash after delete failed ({}): {}", e, escape_path(file_path));
+                self.record_progress(ProgressEvent::file(file_path, 0), &message).await;
+                self.verify_removed(file_path).await;
+                Ok(Ok(true))
+            }
+            Err(e) => Ok(Err(e)),
+        }
+    }
+
+    /// Waits until the next removal is allowed under `max_rate`
+    ///
+    /// Removals are spaced evenly rather than released in bursts; the lock is
+    /// held while sleeping so concurrent jobs queue up behind each other.
+    async fn throttle(&self) {
+        let Some(interval) = self.removal_interval else {
+            return;
+        };
+        let mut next = self.next_removal.lock().await;
+        let now = tokio::time::Instant::now();
+        if *next > now {
+            tokio::time::sleep_until(*next).await;
+        }
+        *next = (*next).max(now) + interval;
+    }
+
+    /// Deletes a directory tree bottom-up using an explicit work queue
+    ///
+    /// Directories are listed (see [`DeletionEngine::list_directory`]) up to
+    /// `dir_jobs` at a time, in `delete_order`. Each one is removed once all of its
+    /// subdirectories are finished, and then its parent is checked in turn,
+    /// so neither the call stack nor the nesting of futures grows with the
+    /// depth of the tree.
+    ///
+    /// Contents are removed concurrently, so the final `remove_dir` can race a
+    /// delete that hasn't landed yet (or an entry created mid-run) and fail
+    /// with "directory not empty". Instead of counting that as an error, the
+    /// directory is rescanned and emptied once more before giving up.
+    ///
+    /// Without `remove_root` only the contents of `dir_path` are deleted.
+    /// Returns `true` if `dir_path` is still there (or, without
+    /// `remove_root`, still has contents), either because it holds
+    /// intentionally kept entries or because something in it (or the
+    /// directory itself) could not be deleted.
+    async fn delete_tree(&self, root: &TraversalRoot<'_>, dir_path: &Path, remove_root: bool) -> Result<bool> {
+        if let Some(ref pool) = self.blocking {
+            return blocking::run(|| self.delete_tree_blocking(pool, root.path, dir_path, remove_root));
+        }
+
+        let mut dirs = vec![Some(PendingDir::new(dir_path.to_path_buf(), None, DirContext::default(), remove_root))];
+        let mut free_slots = Vec::new();
+        let mut queue = VecDeque::from([0]);
+        let mut listings = FuturesUnordered::new();
+        // Listings that completed while a directory was being removed
+        let mut ready = VecDeque::new();
+        loop {
+            while listings.len() < self.dir_jobs() {
+                let next = match self.delete_order {
+                    DeleteOrder::Dfs => queue.pop_back(),
+                    DeleteOrder::Bfs => queue.pop_front(),
+                };
+                let Some(slot) = next else {
+                    break;
+                };
+                let dir = dirs[slot].as_ref().expect(LIVE_SLOT);
+                let (path, context) = (dir.path.clone(), dir.context.clone());
+                listings.push(async move {
+                    // Only the immediate subdirectories of a target are confirmed
+                    if self.interactive && context.depth == 1 && !context.rescan && !self.confirm_subdirectory(&path).await? {
+                        self.stats.increment_skipped();
+                        trace!("Keeping declined directory: {}", escape_path(&path));
+                        return Ok((slot, None));
+                    }
+                    let listing = self.list_directory(root, &path, context).await?;
+                    Ok::<_, anyhow::Error>((slot, Some(listing)))
+                });
+            }
+
+            let result = match ready.pop_front() {
+                Some(result) => result,
+                None => match listings.next().await {
+                    Some(result) => result,
+                    // Unreachable: the starting directory finishes last and returns
+                    None => return Ok(true),
+                },
+            };
+            let (slot, listing) = result?;
+            let dir = dirs[slot].as_mut().expect(LIVE_SLOT);
+            let mut children = Vec::new();
+            match listing {
+                Some(listing) => {
+                    dir.kept |= listing.kept;
+                    dir.remove &= !listing.replaced_by_file;
+                    for (path, ignored, included) in listing.subdirs {
+                        // Each immediate child of the root starts its own breakdown entry
+                        let breakdown = (self.track_breakdown && dir.context.depth == 0)
+                            .then(|| (Arc::new(SubtreeTally::default()), Instant::now()));
+                        let context = DirContext {
+                            depth: dir.context.depth + 1,
+                            tally: breakdown.as_ref().map(|(tally, _)| Arc::clone(tally)).or_else(|| dir.context.tally.clone()),
+                            gitignores: listing.gitignores.clone(),
+                            in_ignored: dir.context.in_ignored || ignored,
+                            in_included: dir.context.in_included || included,
+                            rescan: false,
+                        };
+                        let mut child = PendingDir::new(path, Some(slot), context, true);
+                        child.breakdown = breakdown;
+                        children.push(child);
+                    }
+                }
+                None => {
+                    dir.kept = true;
+                    dir.breakdown = None;
+                }
+            }
+            dir.pending = children.len();
+
+            if children.is_empty() {
+                // The other listings may hold the job permits the removal
+                // waits for, so they have to keep running in the meantime
+                let mut finish = Box::pin(self.finish_directory(&mut dirs, &mut free_slots, &mut queue, slot));
+                let finished = loop {
+                    tokio::select! {
+                        finished = &mut finish => break finished?,
+                        Some(result) = listings.next(), if !listings.is_empty() => ready.push_back(result),
+                    }
+                };
+                if let Some(still_there) = finished {
+                    return Ok(still_there);
+                }
+            }
+            for child in children {
+                let child_slot = match free_slots.pop() {
+                    Some(free) => {
+                        dirs[free] = Some(child);
+                        free
+                    }
+                    None => {
+                        dirs.push(Some(child));
+                        dirs.len() - 1
+                    }
+                };
+                queue.push_back(child_slot);
+            }
+        }
+    }
+
+    /// Removes a directory whose subdirectories are all finished, then walks
+    /// up through every parent that this leaves finished as well
+    ///
+    /// A directory that fails with "directory not empty" for the first time
+    /// is queued for a rescan instead. Returns whether the starting directory
+    /// of [`DeletionEngine::delete_tree`] is still there once it is reached,
+    /// or `None` while other work is still outstanding.
+    async fn finish_directory(
+        &self,
+        dirs: &mut [Option<PendingDir>],
+        free_slots: &mut Vec<usize>,
+        queue: &mut VecDeque<usize>,
+        mut slot: usize,
+    ) -> Result<Option<bool>> {
+        loop {
+            let dir = dirs[slot].as_mut().expect(LIVE_SLOT);
+            // After cancellation everything not yet removed counts as kept, so no
+            // parent directory is attempted
+            let kept = dir.kept || self.is_cancelled();
+            let still_there = if kept || !dir.remove {
+                kept
+            } else if self.dry_run {
+                !self.remove_directory(&dir.path).await?
+            } else {
+                match self.remove_empty_directory(&dir.path).await? {
+                    Err(e) if e.kind() == io::ErrorKind::DirectoryNotEmpty && !dir.rescanned => {
+                        trace!("Directory not empty, rescanning: {}", escape_path(&dir.path));
+                        dir.rescanned = true;
+                        dir.context.rescan = true;
+                        queue.push_back(slot);
+                        return Ok(None);
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::NotADirectory && !self.has_filters() => {
+                        let tally = dir.context.tally.clone();
+                        !self.remove_replaced_directory(&dir.path, tally.as_deref()).await?
+                    }
+                    result => !self.record_directory_removal(&dir.path, result).await?,
+                }
+            };
+
+            let dir = dirs[slot].take().expect(LIVE_SLOT);
+            free_slots.push(slot);
+            if let Some((tally, started)) = dir.breakdown {
+                self.stats.record_breakdown(DirectoryBreakdown {
+                    path: dir.path,
+                    files: tally.files.load(Ordering::Relaxed),
+                    bytes: tally.bytes.load(Ordering::Relaxed),
+                    elapsed: started.elapsed(),
+                });
+            }
+
+            let Some(parent) = dir.parent else {
+                return Ok(Some(still_there));
+            };
+            let parent_dir = dirs[parent].as_mut().expect(LIVE_SLOT);
+            parent_dir.kept |= still_there;
+            parent_dir.pending -= 1;
+            if parent_dir.pending > 0 {
+                return Ok(None);
+            }
+            slot = parent;
+        }
+    }
+
+    /// Removes an empty directory and updates statistics
+    ///
+    /// In dry-run mode only the statistics are updated. Returns whether the
+    /// directory is gone.
+    async fn remove_directory(&self, dir_path: &Path) -> Result<bool> {
+        if self.dry_run {
+            self.stats.increment_dirs();
+            let message = format!("Would delete directory: {}", escape_path(dir_path));
+            self.record_progress(ProgressEvent::directory(dir_path), &message).await;
+            return Ok(true);
+        }
+
+        let result = self.remove_empty_directory(dir_path).await?;
+        self.record_directory_removal(dir_path, result).await
+    }
+
+    /// Issues the actual directory removal, with retries, under a job permit
+    ///
+    /// The outer `Result` only fails if the job limiter is closed; the inner
+    /// one is the outcome of the removal itself.
+    async fn remove_empty_directory(&self, dir_path: &Path) -> Result<io::Result<()>> {
+        let _permit = self.job_limiter.acquire().await?;
+        Ok(self
+            .with_retries(dir_path, || async {
+                if self.trash {
+                    move_to_trash(dir_path).await
+                } else {
+                    fs::remove_dir(long_path(dir_path)).await
+                }
+            })
+            .await)
+    }
+
+    /// Updates statistics for the outcome of a directory removal, returning
+    /// whether it succeeded
+    async fn record_directory_removal(&self, dir_path: &Path, result: io::Result<()>) -> Result<bool> {
+        match result {
+            Ok(()) => {
+                self.stats.increment_dirs();
+                let message = format!("Deleted directory: {}", escape_path(dir_path));
+                self.record_progress(ProgressEvent::directory(dir_path), &message).await;
+                self.verify_removed(dir_path).await;
+                Ok(true)
+            }
+            Err(e) => {
+                self.record_error("Failed to delete directory", dir_path, &e).await?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// With `verify` set, re-stats an entry whose removal just succeeded and
+    /// counts it as a verification failure if it is somehow still there
+    ///
+    /// Anything but "not found" (including a failed stat) counts as present,
+    /// since the point is to not trust a success we can't confirm.
+    async fn verify_removed(&self, path: &Path) {
+        if !self.verify {
+            return;
+        }
+        match fs::symlink_metadata(long_path(path)).await {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            _ => {
+                self.stats.record_survivor(path);
+                let message = format!("Still present after deletion: {}", escape_path(path));
+                warn!("{}", message);
+                self.log_to_file("ERROR", &message).await;
+            }
+        }
+    }
+
+    /// Runs a removal, retrying transient failures with exponential backoff
+    ///
+    /// If the retries are exhausted on a permission error and `force` is set,
+    /// the entry is made removable and attempted one last time. Only the final
+    /// outcome is returned, so the caller counts at most one error per entry no
+    /// matter how many attempts were made.
+    async fn with_retries<F, Fut>(&self, path: &Path, operation: F) -> io::Result<()>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = io::Result<()>>,
+    {
+        let mut attempt = 0;
+        loop {
+            let in_flight = self.stats.begin_removal();
+            let result = operation().await;
+            drop(in_flight);
+            match result {
+                Err(e) if attempt < self.retries && is_transient(&e) => {
+                    let delay = RETRY_BASE_DELAY * 2u32.saturating_pow(attempt);
+                    attempt += 1;
+                    trace!(
+                        "Retrying {} in {}ms (attempt {}/{}): {}",
+                        escape_path(path),
+                        delay.as_millis(),
+                        attempt,
+                        self.retries,
+                        e
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                Err(e) if e.kind() == io::ErrorKind::PermissionDenied && self.may_force(path) => {
+                    trace!("Clearing read-only attribute on {}", escape_path(path));
+                    if let Err(clear_error) = make_removable(path).await {
+                        trace!(
+                            "Failed to clear read-only attribute on {}: {}",
+                            escape_path(path),
+                            clear_error
+                        );
+                        return Err(e);
+                    }
+                    let _in_flight = self.stats.begin_removal();
+                    return operation().await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Whether `force` may make `path` removable after a permission error
+    ///
+    /// On Unix that means loosening the parent directory, which for a target
+    /// lies outside the tree the user named, so targets keep their error.
+    fn may_force(&self, path: &Path) -> bool {
+        self.force && (cfg!(windows) || self.targets.lock().is_ok_and(|targets| !targets.contains(path)))
+    }
+
+    /// Records `count` finished entries on the progress bar
+    ///
+    /// A counted bar is advanced but never past its pre-scanned length, since
+    /// entries created after the scan would otherwise push it beyond 100%.
+    fn advance_progress(&self, count: u64, message: &str) {
+        if self.progress_stream {
+            self.stream_progress(false);
+        }
+
+        let Some(ref pb) = self.progress_bar else {
+            return;
+        };
+
+        pb.set_message(message.to_string());
+        if self.counted_progress {
+            let remaining = pb.length().unwrap_or(0).saturating_sub(pb.position());
+            pb.inc(count.min(remaining));
+            self.sample_throughput(pb);
+        } else {
+            pb.tick();
+        }
+    }
+
+    /// Refreshes the live files/sec and bytes/sec readout on a counted bar
+    ///
+    /// Only one caller per [`RATE_SAMPLE_INTERVAL`] does the work, so the
+    /// readout costs a couple of atomic loads on the hot path.
+    fn sample_throughput(&self, pb: &ProgressBar) {
+        let elapsed = pb.elapsed();
+        let now_ms = elapsed.as_millis() as u64;
+        let last_ms = self.last_rate_sample.load(Ordering::Relaxed);
+        if now_ms.saturating_sub(last_ms) < RATE_SAMPLE_INTERVAL.as_millis() as u64 {
+            return;
+        }
+        if self
+            .last_rate_sample
+            .compare_exchange(last_ms, now_ms, Ordering::Relaxed, Ordering::Relaxed)
+            .is_err()
+        {
+            return; // Another task is already refreshing it
+        }
+
+        let secs = elapsed.as_secs_f64();
+        let files_per_sec = self.stats.files_deleted() as f64 / secs;
+        let bytes_per_sec = (self.stats.bytes_freed() as f64 / secs) as u64;
+        pb.set_prefix(format!("{:.0} files/s, {}/s", files_per_sec, HumanBytes(bytes_per_sec)));
+    }
+
+    /// Writes a `--progress-stream` JSON line to stderr
+    ///
+    /// Events are throttled to one per [`STREAM_EVENT_INTERVAL`], or sooner
+    /// once [`STREAM_EVENT_FILES`] more files are gone; `force` bypasses this
+    /// for the final event.
+    fn stream_progress(&self, force: bool) {
+        let elapsed = self.started.elapsed();
+        let now_ms = elapsed.as_millis() as u64;
+        let files = self.stats.files_deleted();
+        let last_ms = self.last_stream_event.load(Ordering::Relaxed);
+        if !force {
+            let due = now_ms.saturating_sub(last_ms) >= STREAM_EVENT_INTERVAL.as_millis() as u64
+                || files.saturating_sub(self.last_stream_files.load(Ordering::Relaxed)) >= STREAM_EVENT_FILES;
+            if !due
+                || self
+                    .last_stream_event
+                    .compare_exchange(last_ms, now_ms, Ordering::Relaxed, Ordering::Relaxed)
+                    .is_err()
+            {
+                return;
+            }
+        }
+        self.last_stream_files.store(files, Ordering::Relaxed);
+
+        let event = StreamEvent {
+            files,
+            bytes: self.stats.bytes_freed(),
+            elapsed: elapsed.as_secs_f64(),
+        };
+        if let Ok(line) = serde_json::to_string(&event) {
+            eprintln!("{}", line);
+        }
+    }
+
+    /// Reports a completed operation to the progress callback, the progress
+    /// bar and the log file
+    async fn record_progress(&self, event: ProgressEvent<'_>, message: &str) {
+        self.report_progress(&event, message);
+        self.log_to_file("INFO", message).await;
+    }
+
+    /// [`DeletionEngine::record_progress`] without the log file, which can
+    /// only be written from async code
+    fn report_progress(&self, event: &ProgressEvent<'_>, message: &str) {
+        if let Some(ref on_progress) = self.on_progress {
+            (on_progress.0)(event);
+        }
+        self.advance_progress(event.entries, message);
+        // -v follows the directories, -vv every single file
+        match event.kind {
+            ProgressKind::File => debug!("{}", message),
+            _ => info!("{}", message),
+        }
+    }
+
+    /// Counts a failed operation and reports it to the verbose output and log file
+    ///
+    /// With `fail_fast` the failure is also returned as an error, which the
+    /// callers propagate to abort the whole traversal.
+    async fn record_error(&self, action: &str, path: &Path, error: &io::Error) -> Result<()> {
+        let message = self.report_error(action, path, error);
+        self.log_to_file("ERROR", &message).await;
+        self.check_fail_fast(&message)
+    }
+
+    /// [`DeletionEngine::record_error`] without the log file or `fail_fast`,
+    /// returning the reported message
+    fn report_error(&self, action: &str, path: &Path, error: &io::Error) -> String {
+        self.stats.increment_errors(ErrorCategory::of(error));
+        self.stats.record_failure(path, error);
+        if let Some(ref on_progress) = self.on_progress {
+            (on_progress.0)(&ProgressEvent::error(path, error));
+        }
+        let message = format!("{} {}: {}", action, escape_path(path), error);
+        warn!("{}", message);
+        message
+    }
+
+    /// Turns a reported failure into an error when `fail_fast` is set
+    fn check_fail_fast(&self, message: &str) -> Result<()> {
+        if self.fail_fast {
+            anyhow::bail!("{} (stopping because of --fail-fast)", message);
+        }
+        Ok(())
+    }
+
+    /// Appends a timestamped line to the `--log-file`, if one is configured
+    ///
+    /// Write failures are ignored so a full disk can't abort the deletion.
+    async fn log_to_file(&self, level: &str, message: &str) {
+        let Some(ref log_file) = self.log_file else {
+            return;
+        };
+
+        let line = format!(
+            "{} {:<5} {}\n",
+            humantime::format_rfc3339_millis(SystemTime::now()),
+            level,
+            message
+        );
+        let _ = log_file.lock().await.write_all(line.as_bytes()).await;
+    }
+
+    /// Flushes buffered `--log-file` output to disk
+    ///
+    /// Call this before the process exits; buffered lines are lost otherwise.
+    pub async fn flush_log(&self) -> Result<()> {
+        if let Some(ref log_file) = self.log_file {
+            log_file.lock().await.flush().await.context("Failed to flush log file")?;
+        }
+        Ok(())
+    }
+
+    /// Returns the current deletion statistics
+    pub fn get_stats(&self) -> Arc<DeletionStats> {
+        Arc::clone(&self.stats)
+    }
+
+    /// Stops the run as soon as possible
+    ///
+    /// Deletions already in flight finish and are counted; nothing new is
+    /// started, and directories that still have contents are left in place.
+    /// Safe to call from another task, e.g. a Ctrl-C handler.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns whether [`DeletionEngine::cancel`] has been called, or the run
+    /// stopped itself after reaching `min_free`
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Returns whether the run stopped early because `min_free` bytes were freed
+    pub fn min_free_reached(&self) -> bool {
+        self.min_free_reached.load(Ordering::Relaxed)
+    }
+
+    /// Stops the run once the freed bytes reach `min_free`
+    fn check_min_free(&self) {
+        if self.min_free.is_some_and(|target| self.stats.bytes_freed() >= target)
+            && !self.min_free_reached.swap(true, Ordering::Relaxed)
+        {
+            trace!("Freed the requested space, stopping");
+            self.cancel();
+        }
+    }
+
+    /// Finishes the progress spinner or bar, if one is shown, and writes the
+    /// final `--progress-stream` event
+    pub fn finish_progress(&self, message: &str) {
+        if self.progress_stream {
+            self.stream_progress(true);
+        }
+        if let Some(ref pb) = self.progress_bar {
+            pb.finish_with_message(message.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fills every counter and list of a fresh `DeletionStats`, scaled by `n`
+    fn filled(n: u64, name: &str) -> Arc<DeletionStats> {
+        let stats = DeletionStats::new();
+        let path = PathBuf::from(name);
+        for _ in 0..n {
+            stats.increment_files();
+            stats.increment_dirs();
+            stats.add_bytes(100);
+            stats.increment_skipped();
+            stats.increment_mounts_skipped();
+            stats.increment_symlinks_skipped();
+            stats.increment_trash_fallbacks();
+            stats.increment_backup_renames();
+            stats.increment_backup_copies();
+            stats.increment_placeholders();
+            stats.increment_fs_calls();
+        }
+        stats.increment_errors(ErrorCategory::PermissionDenied);
+        stats.increment_errors(ErrorCategory::NotFound);
+        stats.increment_errors(ErrorCategory::InUse);
+        stats.increment_errors(ErrorCategory::Other);
+        stats.record_failure(&path, &io::Error::from(io::ErrorKind::PermissionDenied));
+        stats.record_survivor(&path);
+        stats.record_empty_dir(&path);
+        stats.record_special_file(&path);
+        stats.record_special_kept(&path);
+        stats.record_kept_root(&path, "excluded");
+        stats.record_breakdown(DirectoryBreakdown {
+            path: path.clone(),
+            files: n,
+            bytes: 100 * n,
+            elapsed: Duration::from_millis(n),
+        });
+        let in_flight: Vec<_> = (0..n).map(|_| stats.begin_removal()).collect();
+        drop(in_flight);
+        stats
+    }
+
+    #[test]
+    fn merge_from_adds_counters_and_keeps_the_highest_peak() {
+        let total = filled(1, "a");
+        total.merge_from(&filled(3, "b"));
+
+        assert_eq!(total.files_deleted(), 4);
+        assert_eq!(total.dirs_deleted(), 4);
+        assert_eq!(total.bytes_freed(), 400);
+        assert_eq!(total.skipped(), 4);
+        assert_eq!(total.mounts_skipped(), 4);
+        assert_eq!(total.symlinks_skipped(), 4);
+        assert_eq!(total.trash_fallbacks(), 4);
+        assert_eq!(total.backup_renames(), 4);
+        assert_eq!(total.backup_copies(), 4);
+        assert_eq!(total.placeholders_created(), 4);
+        // One call per increment plus one per removal
+        assert_eq!(total.syscalls(), 8);
+        assert_eq!(total.errors_encountered(), 8);
+        for category in [ErrorCategory::PermissionDenied, ErrorCategory::NotFound, ErrorCategory::InUse, ErrorCategory::Other] {
+            assert_eq!(total.errors_in(category), 2, "{:?}", category);
+        }
+        assert_eq!(total.verification_failures(), 2);
+        assert_eq!(total.peak_concurrency(), 3);
+
+        let paths = vec![PathBuf::from("a"), PathBuf::from("b")];
+        let failed: Vec<PathBuf> = total.failures().into_iter().map(|(path, _)| path).collect();
+        assert_eq!(failed, paths);
+        assert_eq!(total.survivors(), paths);
+        assert_eq!(total.empty_dirs(), paths);
+        assert_eq!(total.special_files(), paths);
+        assert_eq!(total.special_kept(), paths);
+        assert_eq!(total.kept_roots(), vec![(paths[0].clone(), "excluded"), (paths[1].clone(), "excluded")]);
+        // The breakdown comes back largest first
+        let breakdown: Vec<(PathBuf, u64, u64)> =
+            total.breakdown().into_iter().map(|entry| (entry.path, entry.files, entry.bytes)).collect();
+        assert_eq!(breakdown, vec![(paths[1].clone(), 3, 300), (paths[0].clone(), 1, 100)]);
+    }
+
+    #[test]
+    fn merge_from_itself_doubles_instead_of_deadlocking() {
+        let stats = filled(2, "a");
+        stats.merge_from(&stats);
+
+        assert_eq!(stats.files_deleted(), 4);
+        assert_eq!(stats.bytes_freed(), 400);
+        assert_eq!(stats.errors_encountered(), 8);
+        assert_eq!(stats.failures().len(), 2);
+        assert_eq!(stats.survivors().len(), 2);
+        assert_eq!(stats.breakdown().len(), 2);
+    }
+}
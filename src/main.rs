@@ -1,12 +1,84 @@
+mod bench;
+mod config;
+
 use anyhow::{Context, Result};
-use clap::Parser;
+use clap::{ArgAction, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
 use colored::Colorize;
-use indicatif::{ProgressBar, ProgressStyle};
-use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicU64, Ordering};
+use fastdel::{
+    contains_dir, escape_path, free_space, preview_tree, refuse_file_as_directory, refuse_symlink_root, scan_tree, write_checksum_manifest, write_manifest, ConcurrencyModel, DeleteOptions, DeleteOrder, DeletionEngine, DeletionStats, DeletionSummary, ErrorCategory, FreeSpace, PreviewEntry, ScanTotals, SymlinkPolicy,
+    DEFAULT_MAX_OPEN_DIRS, DEFAULT_SIMPLE_MAX_ENTRIES,
+};
+use std::fmt;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Component, Path, PathBuf};
 use std::sync::Arc;
-use std::time::Instant;
-use tokio::fs;
+use std::time::{Duration, Instant};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::fmt::format::{self, FormatEvent, FormatFields};
+use tracing_subscriber::fmt::FmtContext;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::EnvFilter;
+
+/// Exit code when a target could not be resolved or deleted at all
+const EXIT_FAILURE: i32 = 1;
+
+/// Exit code when every target was processed but some entries could not be
+/// removed. Code 2 is already used by clap for usage errors.
+const EXIT_PARTIAL: i32 = 3;
+
+/// Exit code when `--verify` found entries that were reported deleted but
+/// are still present
+const EXIT_UNVERIFIED: i32 = 4;
+
+/// Exit code when the run was stopped with Ctrl-C (128 + SIGINT, as shells report it)
+const EXIT_INTERRUPTED: i32 = 130;
+
+/// Bits of the `--measure-only-errors` exit code set by each error category
+///
+/// Bit 0 keeps the meaning of [`EXIT_FAILURE`] and bit 1 is left to clap's
+/// usage errors, so those codes still read the same in this mode.
+const ERROR_BITS: &[(ErrorCategory, i32)] = &[
+    (ErrorCategory::PermissionDenied, 1 << 2),
+    (ErrorCategory::InUse, 1 << 3),
+    (ErrorCategory::NotFound, 1 << 4),
+    (ErrorCategory::Other, 1 << 5),
+];
+
+/// Bit of the `--measure-only-errors` exit code set by `--verify` failures
+const UNVERIFIED_BIT: i32 = 1 << 6;
+
+/// Levels shown by `--preview` when no depth is given
+const PREVIEW_DEPTH: &str = "2";
+
+/// System directories refused without `--force`, unless the config file
+/// replaces the list
+#[cfg(windows)]
+const PROTECTED_DIRS: &[&str] = &[
+    r"C:\Windows",
+    r"C:\Program Files",
+    r"C:\Program Files (x86)",
+    r"C:\ProgramData",
+    r"C:\Users",
+];
+#[cfg(not(windows))]
+const PROTECTED_DIRS: &[&str] = &[
+    "/bin", "/boot", "/dev", "/etc", "/home", "/lib", "/lib64", "/opt", "/proc", "/root", "/sbin", "/sys", "/usr",
+    "/var", "/Applications", "/Library", "/System", "/Users",
+];
+
+/// Entries listed per directory by `--preview` before "... (N more)"
+const PREVIEW_WIDTH: usize = 10;
+
+/// Number of directories listed by `--profile`
+const PROFILE_TOP_N: usize = 10;
+
+/// Error categories shown in the summary, each with a suggested fix
+const ERROR_HINTS: &[(ErrorCategory, &str)] = &[
+    (ErrorCategory::PermissionDenied, "(retry with --force, or with elevated privileges)"),
+    (ErrorCategory::InUse, "(close the program holding them open and retry)"),
+    (ErrorCategory::NotFound, "(removed by something else during the run)"),
+    (ErrorCategory::Other, "(use --list-errors or -v for details)"),
+];
 
 /// FastDel - A high-performance directory deletion tool
 /// 
@@ -23,235 +95,869 @@ use tokio::fs;
 #[command(name = "fastdel")]
 #[command(about = "Fast directory deletion tool optimized for large folder structures")]
 #[command(version = "1.0")]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
-    /// Path to the directory to delete
-    #[arg(help = "Directory path to delete (e.g., ./node_modules)")]
-    path: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Paths to the directories or files to delete
+    #[arg(required_unless_present_any = ["stdin", "from_file", "resume"], value_name = "PATH")]
+    #[arg(help = "Directory or file paths to delete (e.g., ./node_modules)")]
+    paths: Vec<PathBuf>,
+
+    /// Make Enter confirm the prompt
+    #[arg(long)]
+    #[arg(help = "Treat an empty answer to the confirmation prompt as yes, shown as (Y/n)")]
+    default_yes: bool,
+
+    /// Read target paths from stdin
+    #[arg(long, conflicts_with = "interactive")]
+    #[arg(help = "Also read newline-separated target paths from stdin (requires --yes or --dry-run)")]
+    stdin: bool,
+
+    /// Read target paths from a file
+    #[arg(long, value_name = "PATH")]
+    #[arg(help = "Also read newline-separated target paths from the file at PATH")]
+    from_file: Option<PathBuf>,
 
     /// Skip confirmation prompt
     #[arg(short = 'y', long)]
     #[arg(help = "Skip confirmation prompt and delete immediately")]
     yes: bool,
 
-    /// Verbose output
-    #[arg(short, long)]
-    #[arg(help = "Enable verbose output with detailed progress")]
-    verbose: bool,
-}
+    /// Confirm each top-level subdirectory
+    #[arg(short = 'i', long)]
+    #[arg(help = "Ask before deleting each immediate subdirectory of a target [y/N/a]")]
+    interactive: bool,
+
+    /// Verbose output; repeat for more detail
+    #[arg(short, long, action = ArgAction::Count)]
+    #[arg(help = "Enable verbose output: -v lists removed directories, -vv also every file, -vvv adds skipped entries and timings")]
+    verbose: u8,
+
+    /// Suppress all non-error output
+    #[arg(short, long, conflicts_with_all = ["verbose", "progress", "json"])]
+    #[arg(help = "Suppress banners, progress and summary; only errors are printed (to stderr)")]
+    quiet: bool,
+
+    /// When to use colored output
+    #[arg(long, value_enum, value_name = "WHEN", default_value_t = ColorChoice::Auto)]
+    #[arg(help = "Color the output: auto (only when stdout is a terminal and NO_COLOR is unset), always or never")]
+    color: ColorChoice,
+
+    /// Maximum number of concurrent delete operations
+    #[arg(short = 'j', long, value_name = "N")]
+    #[arg(help = "Maximum number of concurrent delete operations [default: number of CPUs]")]
+    jobs: Option<usize>,
+
+    /// Maximum number of directories open at once
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_MAX_OPEN_DIRS)]
+    #[arg(help = "Maximum number of directories being read at once; lower it on \"too many open files\" errors")]
+    max_open_dirs: usize,
+
+    /// Largest target removed with a single blocking call
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_SIMPLE_MAX_ENTRIES)]
+    #[arg(help = "Remove targets with at most N entries using one blocking remove_dir_all, which beats the concurrent engine on small trees; 0 disables")]
+    simple_max_entries: u64,
+
+    /// Always use the single blocking call
+    #[arg(long, conflicts_with = "simple_max_entries")]
+    #[arg(help = "Remove every target with one blocking remove_dir_all whatever its size, unless a filter or per-entry option needs the engine")]
+    simple: bool,
+
+    /// Cap the number of files removed per second
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    #[arg(help = "Remove at most N files per second across all jobs, to spare shared or network storage")]
+    max_rate: Option<u32>,
+
+    /// Where concurrency is applied
+    #[arg(long, value_enum, value_name = "MODEL", default_value_t = Concurrency::Both)]
+    #[arg(help = "Run file unlinks concurrently (files), sibling directories concurrently (dirs), or both")]
+    concurrency_model: Concurrency,
+
+    /// Traversal order of the directories
+    #[arg(long, value_enum, value_name = "ORDER", default_value_t = Order::Dfs)]
+    #[arg(help = "List directories depth-first (dfs) or breadth-first (bfs), which deletes shallow files sooner")]
+    delete_order: Order,
+
+    /// Walk trees with std::fs on plain threads
+    #[arg(long)]
+    #[arg(help = "Walk each tree with std::fs on a work-stealing pool of --jobs threads instead of tokio's async filesystem calls; can be faster on metadata-heavy trees (see README)")]
+    blocking: bool,
+
+    /// Delete all targets at once
+    #[arg(long)]
+    #[arg(help = "Delete multiple targets concurrently instead of one after another, sharing the --jobs limit; helps when they are on different disks")]
+    parallel_roots: bool,
+
+    /// Report what would be deleted without deleting anything
+    #[arg(short = 'n', long)]
+    #[arg(help = "Walk the tree and report what would be deleted without removing anything")]
+    dry_run: bool,
+
+    /// Only print the size of the targets
+    #[arg(long, conflicts_with_all = ["trash", "interactive"])]
+    #[arg(help = "Only count files, directories and bytes, like du; implies --dry-run and never prompts")]
+    total_only: bool,
+
+    /// Move targets to the OS trash instead of deleting them permanently
+    #[arg(long, conflicts_with = "dry_run")]
+    #[arg(help = "Move targets to the OS trash/recycle bin instead of deleting them permanently")]
+    trash: bool,
+
+    /// Fall back to the trash for files that can't be deleted
+    #[arg(long, conflicts_with_all = ["trash", "dry_run"])]
+    #[arg(help = "Move files that can't be deleted permanently (e.g. locked ones) to the trash instead of failing")]
+    trash_on_error: bool,
+
+    /// Move targets into a backup directory instead of deleting them
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["trash", "trash_on_error", "dry_run", "total_only", "keep_root", "interactive"])]
+    #[arg(help = "Move each target into a timestamped folder under DIR instead of deleting it (a fast rename on the same filesystem)")]
+    backup: Option<PathBuf>,
+
+    /// Glob patterns for entries to keep
+    #[arg(long, value_name = "GLOB")]
+    #[arg(help = "Keep entries matching this glob (repeatable, e.g. --exclude \"*.log\")")]
+    exclude: Vec<String>,
+
+    /// Files listing more glob patterns for entries to keep
+    #[arg(long, value_name = "FILE")]
+    #[arg(help = "Keep entries matching the globs listed in FILE, one per line; # starts a comment (repeatable)")]
+    exclude_from: Vec<PathBuf>,
+
+    /// Glob patterns for the only entries to delete
+    #[arg(long, value_name = "GLOB")]
+    #[arg(help = "Only delete entries matching this glob (repeatable, e.g. --include \"*.tmp\"); --exclude wins over --include")]
+    include: Vec<String>,
+
+    /// Regex the full path of each deleted file must match
+    #[arg(long, value_name = "REGEX")]
+    #[arg(help = "Only delete files whose full path matches REGEX (case-sensitive; prefix (?i) to ignore case)")]
+    path_filter: Option<String>,
+
+    /// Invert `--path-filter`
+    #[arg(long, requires = "path_filter")]
+    #[arg(help = "Only delete files whose full path does NOT match --path-filter")]
+    path_filter_invert: bool,
+
+    /// Deepest directory level to descend into
+    #[arg(long, value_name = "N")]
+    #[arg(help = "Only descend N directory levels below the target; 0 deletes just the root's own files")]
+    max_depth: Option<usize>,
+
+    /// Empty the target but keep the directory itself
+    #[arg(long)]
+    #[arg(help = "Delete the contents of the target but keep the directory itself")]
+    keep_root: bool,
+
+    /// Leave a placeholder file in the emptied root
+    #[arg(long, value_name = "NAME", num_args = 0..=1, default_missing_value = ".gitkeep", requires = "keep_root")]
+    #[arg(help = "With --keep-root, create an empty placeholder file (default .gitkeep) once the root was fully emptied")]
+    keep_file: Option<String>,
+
+    /// Print the final summary as JSON
+    #[arg(long)]
+    #[arg(help = "Print only a JSON summary on stdout; progress and diagnostics go to stderr")]
+    json: bool,
+
+    /// Also write the final summary as JSON to a file
+    #[arg(long, value_name = "FILE")]
+    #[arg(help = "Also write the JSON summary to FILE, keeping the normal console output")]
+    summary_json: Option<PathBuf>,
+
+    /// How to treat symlinks inside the targets
+    #[arg(long, value_enum, value_name = "POLICY")]
+    #[arg(help = "What to do with symlinks: skip (keep them), unlink (remove the link only) or follow (descend, dangerous) [default: unlink]")]
+    on_symlink: Option<OnSymlink>,
+
+    /// Descend into symlinked directories
+    #[arg(long, conflicts_with = "on_symlink")]
+    #[arg(help = "Shorthand for --on-symlink follow (dangerous)")]
+    follow_symlinks: bool,
+
+    /// Resolve targets that are symlinks, but nothing below them
+    #[arg(long, conflicts_with_all = ["follow_symlinks", "no_canonicalize"])]
+    #[arg(help = "Delete what a target symlink points to, while symlinks inside it are still handled by --on-symlink")]
+    dereference_root_only: bool,
 
-/// Statistics tracking for the deletion operation
-#[derive(Debug, Default)]
-struct DeletionStats {
-    files_deleted: AtomicU64,
-    dirs_deleted: AtomicU64,
-    errors_encountered: AtomicU64,
-    bytes_freed: AtomicU64,
+    /// Use target paths as given instead of resolving them
+    #[arg(long, conflicts_with_all = ["follow_symlinks", "backup"])]
+    #[arg(help = "Don't resolve symlinks in target paths, e.g. to remove a broken symlink; weakens the safety checks (see README)")]
+    no_canonicalize: bool,
+
+    /// Skip per-entry metadata for raw speed
+    #[arg(long)]
+    #[arg(help = "UNSAFE: classify entries by listing type only, without stat'ing them; sizes aren't measured and metadata-based options are refused. For throwaway directories")]
+    assume_empty_fast_delete: bool,
+
+    /// Stay on the filesystem of each target
+    #[arg(long)]
+    #[arg(help = "Skip directories on a different filesystem than the target (like rm --one-file-system)")]
+    one_file_system: bool,
+
+    /// Show a progress bar with a real total
+    #[arg(long)]
+    #[arg(help = "Pre-scan each target and show a progress bar with a total and ETA")]
+    progress: bool,
+
+    /// Custom layout for the progress bar or spinner
+    #[arg(long, value_name = "TEMPLATE")]
+    #[arg(help = "Replace the progress bar or spinner layout with an indicatif template, e.g. \"{spinner} {pos}/{len} {msg}\"")]
+    progress_template: Option<String>,
+
+    /// Retries for transient failures
+    #[arg(long, value_name = "N", default_value_t = 3)]
+    #[arg(help = "Retry removals that fail with transient errors (e.g. file in use) up to N times")]
+    retries: u32,
+
+    /// Clear read-only attributes that block deletion
+    #[arg(short = 'f', long)]
+    #[arg(help = "Clear read-only attributes and retry when a removal is denied")]
+    force: bool,
+
+    /// Only delete files older than this age
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    #[arg(help = "Only delete files last modified longer ago than DURATION (e.g. 30m, 24h, 7d)")]
+    older_than: Option<Duration>,
+
+    /// Only delete files newer than this age
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    #[arg(help = "Only delete files last modified less than DURATION ago (e.g. 12h); with --older-than, a window")]
+    newer_than: Option<Duration>,
+
+    /// Only delete files larger than this size
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    #[arg(help = "Only delete files larger than SIZE (e.g. 500K, 10M, 1G)")]
+    larger_than: Option<u64>,
+
+    /// Only delete files smaller than this size
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    #[arg(help = "Only delete files smaller than SIZE (e.g. 500K, 10M, 1G)")]
+    smaller_than: Option<u64>,
+
+    /// Keep files larger than this size
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    #[arg(help = "Keep every file larger than SIZE (e.g. 1G), along with the directories holding it")]
+    exclude_larger_than: Option<u64>,
+
+    /// Stop once this much space has been freed
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    #[arg(help = "Stop deleting once SIZE has been freed (e.g. 2G); in-flight deletions may overshoot it")]
+    min_free: Option<u64>,
+
+    /// Delete the biggest files first
+    #[arg(long, conflicts_with = "interactive")]
+    #[arg(help = "Pre-scan and delete the largest files first to recover space sooner (pairs well with --min-free)")]
+    largest_first: bool,
+
+    /// Print a per-directory breakdown in the summary
+    #[arg(long)]
+    #[arg(help = "Show files, space and time per top-level subdirectory in the summary")]
+    stats_by_dir: bool,
+
+    /// Report the slowest top-level subdirectories
+    #[arg(long)]
+    #[arg(help = "Time each top-level subdirectory and list the 10 slowest in the summary")]
+    profile: bool,
+
+    /// Depth below which targets need typed confirmation
+    #[arg(long, value_name = "N", default_value_t = 2)]
+    #[arg(help = "Require retyping the path to confirm targets fewer than N levels below the filesystem root")]
+    confirm_depth: usize,
+
+    /// Count the targets before prompting
+    #[arg(long)]
+    #[arg(help = "Pre-scan the targets so the confirmation prompt shows their file count and size")]
+    count_before: bool,
+
+    /// Print a tree of the targets before deleting
+    #[arg(long, value_name = "DEPTH", num_args = 0..=1, default_missing_value = PREVIEW_DEPTH)]
+    #[arg(help = "Before deleting, print a tree of the first DEPTH levels (default 2) with sizes; wide directories are truncated")]
+    preview: Option<usize>,
+
+    /// Print the whole tree of the targets before deleting
+    #[arg(long, conflicts_with = "preview")]
+    #[arg(help = "Like --preview, but list every level and every entry")]
+    preview_all: bool,
+
+    /// Only delete entries ignored by .gitignore
+    #[arg(long)]
+    #[arg(help = "Only delete entries matched by .gitignore rules inside the target, like git clean -X")]
+    gitignore: bool,
+
+    /// Only delete empty directories
+    #[arg(long)]
+    #[arg(help = "Only remove directories that are empty or become empty; files are never deleted")]
+    empty_dirs_only: bool,
+
+    /// Skip the confirmation prompt for small deletions
+    #[arg(long, value_name = "N|SIZE", value_parser = parse_confirm_threshold)]
+    #[arg(help = "Skip the prompt when the targets hold fewer than N files, or less than SIZE if a unit is given (e.g. 1000, 50M)")]
+    no_confirm_under: Option<ConfirmThreshold>,
+
+    /// Stream machine-readable progress to stderr
+    #[arg(long, conflicts_with = "quiet")]
+    #[arg(help = "Write throttled JSON progress lines ({\"files\",\"bytes\",\"elapsed\"}) to stderr for GUI frontends")]
+    progress_stream: bool,
+
+    /// Print a running summary line at a fixed interval
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..), conflicts_with = "quiet")]
+    #[arg(help = "Every SECS seconds, print a timestamped line with files, bytes and rate so far to stderr, for CI logs")]
+    summary_interval: Option<u64>,
+
+    /// Write a listing of every file before deleting
+    #[arg(long, value_name = "PATH")]
+    #[arg(help = "Before deleting, write every file's path, size and mtime to PATH as JSON lines")]
+    manifest: Option<PathBuf>,
+
+    /// Hash every file into the manifest
+    #[arg(long, requires = "manifest")]
+    #[arg(help = "Also record each file's BLAKE3 hash in the --manifest; reads every file in full, which is slow")]
+    checksum_manifest: bool,
+
+    /// Finish an interrupted run from its manifest
+    #[arg(long, value_name = "MANIFEST", conflicts_with_all = ["paths", "stdin", "from_file", "manifest", "backup"])]
+    #[arg(help = "Instead of PATHs, delete the files listed in an earlier run's --manifest that still exist")]
+    resume: Option<PathBuf>,
+
+    /// List every entry that could not be deleted
+    #[arg(long)]
+    #[arg(help = "List every path that could not be deleted, with its error, after the summary")]
+    list_errors: bool,
+
+    /// List directories that were already empty
+    #[arg(long)]
+    #[arg(help = "List every directory that already had no entries, e.g. leftover scaffolding, after the summary")]
+    report_empty: bool,
+
+    /// Warn about files with extended attributes or ACLs
+    #[arg(long)]
+    #[arg(help = "Check every file for extended attributes or POSIX ACLs (Linux and macOS) and list those removed; costs a syscall per file")]
+    warn_special: bool,
+
+    /// Leave FIFOs, sockets and device nodes alone
+    #[arg(long)]
+    #[arg(help = "Keep FIFOs, sockets and block or character devices instead of unlinking them, and list them after the summary")]
+    skip_special: bool,
+
+    /// Append a record of every operation to a file
+    #[arg(long, value_name = "PATH")]
+    #[arg(help = "Append a timestamped record of every deleted path and error to PATH")]
+    log_file: Option<PathBuf>,
+
+    /// Confirm every removed entry is really gone
+    #[arg(long)]
+    #[arg(help = "Re-check every removed entry (and the target root) afterwards and report any that still exist")]
+    verify: bool,
+
+    /// Skip targets that don't exist
+    #[arg(long)]
+    #[arg(help = "Warn about and skip target paths that don't exist instead of failing")]
+    ignore_missing: bool,
+
+    /// Report free disk space before and after
+    #[arg(long)]
+    #[arg(help = "Print the free space on the targets' filesystems before and after deleting")]
+    show_disk: bool,
+
+    /// Stop at the first error
+    #[arg(long)]
+    #[arg(help = "Abort the whole run on the first entry that can't be deleted, instead of continuing past errors")]
+    fail_fast: bool,
+
+    /// Encode the error categories hit in the exit code
+    #[arg(long)]
+    #[arg(help = "Exit with a bitmask of the error categories hit instead of 3 or 4, so CI can branch on the failure type (see README)")]
+    measure_only_errors: bool,
+
+    /// Ignore the config file
+    #[arg(long)]
+    #[arg(help = "Don't load default options from .fastdel.toml or $XDG_CONFIG_HOME/fastdel/config.toml")]
+    no_config: bool,
+
+    /// Replacement for the built-in protected directories; config file only
+    #[arg(skip)]
+    protected: Option<Vec<PathBuf>>,
 }
 
-impl DeletionStats {
-    fn new() -> Arc<Self> {
-        Arc::new(Self::default())
-    }
+/// Auxiliary subcommands; a plain `fastdel <PATH>...` deletes
+#[derive(Subcommand)]
+enum Command {
+    /// Time fastdel against std::fs::remove_dir_all on a synthetic tree
+    #[command(hide = true)]
+    Bench(bench::BenchArgs),
+    /// Print a shell completion script to stdout
+    #[command(hide = true)]
+    Completions {
+        /// Shell to generate the script for
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
+}
 
-    fn increment_files(&self) {
-        self.files_deleted.fetch_add(1, Ordering::Relaxed);
-    }
+/// Setting of `--color`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
 
-    fn increment_dirs(&self) {
-        self.dirs_deleted.fetch_add(1, Ordering::Relaxed);
+impl ColorChoice {
+    /// Forces `colored` on or off for the rest of the run
+    ///
+    /// `auto` still lets `colored` honor `NO_COLOR` and `CLICOLOR` when stdout
+    /// is a terminal, but never writes escape codes into a pipe or file.
+    fn apply(self) {
+        match self {
+            ColorChoice::Always => colored::control::set_override(true),
+            ColorChoice::Never => colored::control::set_override(false),
+            ColorChoice::Auto if !io::stdout().is_terminal() => colored::control::set_override(false),
+            ColorChoice::Auto => {}
+        }
     }
+}
 
-    fn increment_errors(&self) {
-        self.errors_encountered.fetch_add(1, Ordering::Relaxed);
-    }
+/// Setting of `--on-symlink`, mirroring [`SymlinkPolicy`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OnSymlink {
+    Skip,
+    Unlink,
+    Follow,
+}
 
-    fn add_bytes(&self, bytes: u64) {
-        self.bytes_freed.fetch_add(bytes, Ordering::Relaxed);
-    }
+/// Setting of `--concurrency-model`, mirroring [`ConcurrencyModel`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Concurrency {
+    Files,
+    Dirs,
+    Both,
+}
 
-    fn get_summary(&self) -> (u64, u64, u64, u64) {
-        (
-            self.files_deleted.load(Ordering::Relaxed),
-            self.dirs_deleted.load(Ordering::Relaxed),
-            self.errors_encountered.load(Ordering::Relaxed),
-            self.bytes_freed.load(Ordering::Relaxed),
-        )
+impl From<Concurrency> for ConcurrencyModel {
+    fn from(concurrency: Concurrency) -> Self {
+        match concurrency {
+            Concurrency::Files => ConcurrencyModel::Files,
+            Concurrency::Dirs => ConcurrencyModel::Dirs,
+            Concurrency::Both => ConcurrencyModel::Both,
+        }
     }
 }
 
-/// Core deletion engine that handles the recursive directory traversal and deletion
-struct DeletionEngine {
-    stats: Arc<DeletionStats>,
-    progress_bar: Option<ProgressBar>,
-    verbose: bool,
+/// Setting of `--delete-order`, mirroring [`DeleteOrder`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Order {
+    Dfs,
+    Bfs,
 }
 
-impl DeletionEngine {
-    fn new(verbose: bool) -> Self {
-        let progress_bar = if verbose {
-            let pb = ProgressBar::new_spinner();
-            pb.set_style(
-                ProgressStyle::default_spinner()
-                    .template("{spinner:.green} [{elapsed_precise}] {msg}")
-                    .unwrap(),
-            );
-            Some(pb)
-        } else {
-            None
-        };
+impl From<Order> for DeleteOrder {
+    fn from(order: Order) -> Self {
+        match order {
+            Order::Dfs => DeleteOrder::Dfs,
+            Order::Bfs => DeleteOrder::Bfs,
+        }
+    }
+}
 
-        Self {
-            stats: DeletionStats::new(),
-            progress_bar,
-            verbose,
+impl Args {
+    /// The symlink policy selected by `--on-symlink` or `--follow-symlinks`
+    fn symlink_policy(&self) -> SymlinkPolicy {
+        match self.on_symlink {
+            _ if self.follow_symlinks => SymlinkPolicy::Follow,
+            Some(OnSymlink::Skip) => SymlinkPolicy::Skip,
+            Some(OnSymlink::Unlink) => SymlinkPolicy::Unlink,
+            Some(OnSymlink::Follow) => SymlinkPolicy::Follow,
+            None => SymlinkPolicy::default(),
         }
     }
+}
 
-    /// Main entry point for directory deletion
-    /// 
-    /// This function orchestrates the entire deletion process:
-    /// 1. Validates the target path exists and is a directory
-    /// 2. Initiates recursive deletion with proper error handling
-    /// 3. Ensures the root directory is removed last
-    async fn delete_directory(&self, path: &Path) -> Result<()> {
-        // Validate that the path exists and is a directory
-        let metadata = fs::metadata(path).await
-            .with_context(|| format!("Failed to access path: {}", path.display()))?;
+/// Size below which `--no-confirm-under` skips the confirmation prompt
+#[derive(Debug, Clone, Copy)]
+enum ConfirmThreshold {
+    Files(u64),
+    Bytes(u64),
+}
 
-        if !metadata.is_dir() {
-            anyhow::bail!("Path is not a directory: {}", path.display());
+impl ConfirmThreshold {
+    /// Pre-scans the targets and checks whether they fall below the threshold
+    async fn allows(self, paths: &[PathBuf]) -> bool {
+        let (mut files, mut bytes) = (0, 0);
+        for path in paths {
+            let totals = scan_tree(path).await;
+            files += totals.files;
+            bytes += totals.bytes;
         }
 
-        self.log_verbose(&format!("Starting deletion of: {}", path.display()));
+        match self {
+            ConfirmThreshold::Files(limit) => files < limit,
+            ConfirmThreshold::Bytes(limit) => bytes < limit,
+        }
+    }
+}
 
-        // Recursively delete all contents first using concurrent deletion
-        Box::pin(self.delete_directory_contents_concurrent(path)).await?;
+/// Parses a plain file count (`1000`) or a size with a unit (`50M`)
+fn parse_confirm_threshold(input: &str) -> Result<ConfirmThreshold, String> {
+    let input = input.trim();
+    if !input.is_empty() && input.chars().all(|c| c.is_ascii_digit()) {
+        let files = input
+            .parse()
+            .map_err(|_| format!("file count '{}' is too large", input))?;
+        return Ok(ConfirmThreshold::Files(files));
+    }
+    parse_size(input).map(ConfirmThreshold::Bytes)
+}
 
-        // Finally, remove the empty root directory
-        self.remove_directory(path).await?;
+/// Returns the user's home directory from the environment, if known
+fn home_dir() -> Option<PathBuf> {
+    std::env::var_os(if cfg!(windows) { "USERPROFILE" } else { "HOME" })
+        .map(PathBuf::from)
+        .and_then(|home| home.canonicalize().ok())
+}
 
-        Ok(())
-    }
+/// Expands a leading `~` and `$VAR`/`${VAR}` references (plus `%VAR%` on
+/// Windows) in a path that didn't come through a shell
+///
+/// Paths the shell already expanded contain none of these and come back
+/// unchanged, as do paths that aren't valid UTF-8. Referencing an unset
+/// variable is an error rather than silently expanding to nothing.
+fn expand_path(path: &Path) -> Result<PathBuf> {
+    let Some(text) = path.to_str() else {
+        return Ok(path.to_path_buf());
+    };
+    #[cfg(windows)]
+    let text = &expand_percent_vars(text);
 
-    /// Recursively deletes all contents of a directory using concurrent operations
-    /// 
-    /// This function uses a depth-first approach with controlled concurrency:
-    /// - Processes all files in the current directory concurrently
-    /// - Recursively processes subdirectories
-    /// - Uses efficient async operations for maximum performance
-    async fn delete_directory_contents_concurrent(&self, dir_path: &Path) -> Result<()> {
-        // Read directory entries
-        let mut entries = match fs::read_dir(dir_path).await {
-            Ok(entries) => entries,
-            Err(e) => {
-                self.stats.increment_errors();
-                self.log_verbose(&format!("Failed to read directory {}: {}", dir_path.display(), e));
-                return Ok(()); // Continue with other operations
-            }
-        };
+    let home = || home_dir().and_then(|home| home.into_os_string().into_string().ok());
+    let expanded = shellexpand::full_with_context(text, home, |name| std::env::var(name).map(Some))
+        .map_err(|e| anyhow::anyhow!("Failed to expand {}: ${}: {}", escape_path(path), e.var_name, e.cause))?;
+    Ok(PathBuf::from(expanded.as_ref()))
+}
 
-        let mut file_paths = Vec::new();
-        let mut dir_paths = Vec::new();
-
-        // Separate files and directories
-        while let Ok(Some(entry)) = entries.next_entry().await {
-            let path = entry.path();
-            match fs::metadata(&path).await {
-                Ok(metadata) => {
-                    if metadata.is_dir() {
-                        dir_paths.push(path);
-                    } else {
-                        file_paths.push((path, metadata.len()));
-                    }
+/// Replaces `%VAR%` with the variable's value, leaving unknown names as
+/// written like `cmd.exe` does
+#[cfg(windows)]
+fn expand_percent_vars(text: &str) -> String {
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('%') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        match after.find('%') {
+            Some(end) if end > 0 => match std::env::var(&after[..end]) {
+                Ok(value) => {
+                    expanded.push_str(&value);
+                    rest = &after[end + 1..];
                 }
-                Err(e) => {
-                    self.stats.increment_errors();
-                    self.log_verbose(&format!("Failed to get metadata for {}: {}", path.display(), e));
+                Err(_) => {
+                    expanded.push('%');
+                    rest = after;
                 }
+            },
+            _ => {
+                expanded.push('%');
+                rest = after;
             }
         }
+    }
+    expanded.push_str(rest);
+    expanded
+}
 
-        // Delete all files concurrently within this directory
-        for (file_path, size) in file_paths {
-            self.remove_file(&file_path, size).await?;
+/// Makes `path` absolute without touching the filesystem, for `--no-canonicalize`
+///
+/// `.` and `..` are removed lexically, so symlinks along the way stay
+/// unresolved and `link/..` means the directory holding `link`, not the
+/// parent of what it points to.
+fn absolute_lexical(path: &Path) -> io::Result<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for component in std::path::absolute(path)?.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
         }
+    }
+    Ok(normalized)
+}
 
-        // Recursively process subdirectories
-        for dir_path in dir_paths {
-            Box::pin(self.delete_directory_contents_concurrent(&dir_path)).await?;
-            self.remove_directory(&dir_path).await?;
-        }
+/// Returns whether `path` is the root of a filesystem, such as `/` or `C:\`
+fn is_filesystem_root(path: &Path) -> bool {
+    path.parent().is_none()
+}
+
+/// Expands and canonicalizes the protected directories, dropping any that
+/// don't exist
+///
+/// Each directory is kept as written as well as resolved, so a
+/// `--no-canonicalize` target spelled like the list still matches.
+/// `configured` replaces [`PROTECTED_DIRS`] when the config file sets
+/// `protected`, so an empty list turns the check off.
+fn protected_dirs(configured: Option<&[PathBuf]>) -> Vec<PathBuf> {
+    let dirs = match configured {
+        Some(dirs) => dirs.to_vec(),
+        None => PROTECTED_DIRS.iter().map(PathBuf::from).collect(),
+    };
+    dirs.iter()
+        .filter_map(|dir| {
+            let expanded = expand_path(dir).ok()?;
+            let resolved = expanded.canonicalize().ok()?;
+            Some([expanded, resolved])
+        })
+        .flatten()
+        .collect()
+}
 
-        Ok(())
+/// Explains why a canonicalized target is refused outright without `--force`
+///
+/// The filesystem root is always refused. The home directory counts as
+/// protected unless the config file replaced the list.
+fn protected_reason(path: &Path, protected: &[PathBuf], configured: bool) -> Option<&'static str> {
+    if is_filesystem_root(path) {
+        Some("the filesystem root")
+    } else if !configured && home_dir().is_some_and(|home| home == path) {
+        Some("your home directory")
+    } else if protected.iter().any(|dir| dir == path) {
+        Some("the protected system directory")
+    } else {
+        None
     }
+}
 
-    /// Removes a single file and updates statistics
-    async fn remove_file(&self, file_path: &Path, size: u64) -> Result<()> {
-        match fs::remove_file(file_path).await {
-            Ok(()) => {
-                self.stats.increment_files();
-                self.stats.add_bytes(size);
-                self.update_progress(&format!("Deleted file: {}", file_path.display()));
-            }
-            Err(e) => {
-                self.stats.increment_errors();
-                self.log_verbose(&format!("Failed to delete file {}: {}", file_path.display(), e));
-            }
+/// Explains why a canonicalized target is too dangerous for a simple y/N prompt
+fn danger_reason(path: &Path, confirm_depth: usize) -> Option<&'static str> {
+    let depth = path
+        .components()
+        .filter(|component| matches!(component, Component::Normal(_)))
+        .count();
+
+    if is_filesystem_root(path) {
+        Some("the filesystem root")
+    } else if home_dir().is_some_and(|home| home == path) {
+        Some("your home directory")
+    } else if depth < confirm_depth {
+        Some("close to the filesystem root")
+    } else {
+        None
+    }
+}
+
+/// Prints a `--preview` tree, one line per entry, indented like `tree`
+fn print_preview(out: &mut dyn Write, entry: &PreviewEntry, prefix: &str, connector: &str) -> io::Result<()> {
+    let name = if prefix.is_empty() && connector.is_empty() {
+        escape_path(&entry.path).to_string()
+    } else {
+        escape_path(Path::new(entry.path.file_name().unwrap_or_default())).to_string()
+    };
+    if entry.is_dir {
+        writeln!(
+            out,
+            "{}{}{} ({}, {} files)",
+            prefix,
+            connector,
+            format!("{}/", name).blue().bold(),
+            format_bytes(entry.totals.bytes),
+            format_count(entry.totals.files)
+        )?;
+    } else {
+        writeln!(out, "{}{}{} ({})", prefix, connector, name, format_bytes(entry.totals.bytes))?;
+    }
+
+    // Children line up under the parent's connector
+    let child_prefix = match connector {
+        "" => prefix.to_string(),
+        "└── " => format!("{}    ", prefix),
+        _ => format!("{}│   ", prefix),
+    };
+    for (index, child) in entry.children.iter().enumerate() {
+        let last = index + 1 == entry.children.len() && entry.hidden == 0;
+        print_preview(out, child, &child_prefix, if last { "└── " } else { "├── " })?;
+    }
+    if entry.hidden > 0 {
+        writeln!(out, "{}└── {}", child_prefix, format!("... ({} more)", entry.hidden).dimmed())?;
+    }
+    Ok(())
+}
+
+/// Prompts user for confirmation before deletion
+///
+/// Dangerous targets (see [`danger_reason`]) must be retyped in full instead of
+/// answering y/N. The prompt is written to `out` so JSON mode can keep it off
+/// stdout.
+fn confirm_deletion(
+    out: &mut dyn Write,
+    paths: &[PathBuf],
+    totals: Option<&[ScanTotals]>,
+    action: &str,
+    confirm_depth: usize,
+    default_yes: bool,
+) -> Result<bool> {
+    writeln!(out, "{}", "⚠️  WARNING".red().bold())?;
+    writeln!(out, "You are about to {}:", action)?;
+    for (index, path) in paths.iter().enumerate() {
+        let path = escape_path(path).to_string().yellow();
+        match totals.map(|totals| totals[index]) {
+            Some(totals) => writeln!(
+                out,
+                "  {} files ({}) in {}",
+                format_count(totals.files),
+                format_bytes(totals.bytes),
+                path
+            )?,
+            None => writeln!(out, "  {}", path)?,
         }
-        Ok(())
     }
+    writeln!(out)?;
 
-    /// Removes an empty directory and updates statistics
-    async fn remove_directory(&self, dir_path: &Path) -> Result<()> {
-        match fs::remove_dir(dir_path).await {
-            Ok(()) => {
-                self.stats.increment_dirs();
-                self.update_progress(&format!("Deleted directory: {}", dir_path.display()));
-            }
-            Err(e) => {
-                self.stats.increment_errors();
-                self.log_verbose(&format!("Failed to delete directory {}: {}", dir_path.display(), e));
+    let dangerous: Vec<_> = paths
+        .iter()
+        .filter_map(|path| danger_reason(path, confirm_depth).map(|reason| (path, reason)))
+        .collect();
+
+    if dangerous.is_empty() {
+        let choices = if default_yes { "(Y/n)" } else { "(y/N)" };
+        loop {
+            write!(out, "Are you sure you want to continue? {}: ", choices)?;
+            out.flush()?;
+
+            // End of input never counts as consent, whatever the default
+            let Some(input) = read_line()? else {
+                writeln!(out)?;
+                return Ok(false);
+            };
+            match input.to_lowercase().as_str() {
+                "" => return Ok(default_yes),
+                "y" | "yes" => return Ok(true),
+                "n" | "no" => return Ok(false),
+                _ => writeln!(out, "Please answer yes or no.")?,
             }
         }
-        Ok(())
     }
 
-    /// Updates progress bar with current operation (if verbose mode is enabled)
-    fn update_progress(&self, message: &str) {
-        if let Some(ref pb) = self.progress_bar {
-            pb.set_message(message.to_string());
-            pb.tick();
+    for (path, reason) in dangerous {
+        writeln!(out, "{}", format!("{} is {}.", escape_path(path), reason).red().bold())?;
+        write!(out, "Type the full path to confirm: ")?;
+        out.flush()?;
+
+        if read_line()?.as_deref() != Some(escape_path(path).to_string().as_str()) {
+            return Ok(false);
         }
     }
 
-    /// Logs verbose messages when verbose mode is enabled
-    fn log_verbose(&self, message: &str) {
-        if self.verbose {
-            println!("{}", message.dimmed());
+    Ok(true)
+}
+
+/// Reads newline-separated paths, ignoring blank lines and surrounding whitespace
+fn read_path_list(reader: impl BufRead) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        if !line.is_empty() {
+            paths.push(PathBuf::from(line));
         }
     }
+    Ok(paths)
+}
 
-    /// Returns the current deletion statistics
-    fn get_stats(&self) -> Arc<DeletionStats> {
-        Arc::clone(&self.stats)
+/// Reads glob patterns from an `--exclude-from` file, one per line
+///
+/// Blank lines and lines starting with `#` are skipped, like `.gitignore`.
+fn read_pattern_file(path: &Path) -> Result<Vec<String>> {
+    let file = std::fs::File::open(path)
+        .with_context(|| format!("Failed to open exclude file: {}", escape_path(path)))?;
+    let mut patterns = Vec::new();
+    for line in io::BufReader::new(file).lines() {
+        let line = line.with_context(|| format!("Failed to read exclude file: {}", escape_path(path)))?;
+        let line = line.trim();
+        if !line.is_empty() && !line.starts_with('#') {
+            patterns.push(line.to_string());
+        }
     }
+    Ok(patterns)
 }
 
-/// Prompts user for confirmation before deletion
-fn confirm_deletion(path: &Path) -> Result<bool> {
-    println!("{}", "⚠️  WARNING".red().bold());
-    println!("You are about to permanently delete:");
-    println!("  {}", path.display().to_string().yellow());
-    println!();
-    print!("Are you sure you want to continue? (y/N): ");
-    
-    use std::io::{self, Write};
-    io::stdout().flush()?;
-    
+/// Reads one trimmed line from stdin, or `None` at end of input
+fn read_line() -> Result<Option<String>> {
     let mut input = String::new();
-    io::stdin().read_line(&mut input)?;
-    
-    Ok(input.trim().to_lowercase() == "y" || input.trim().to_lowercase() == "yes")
+    if io::stdin().read_line(&mut input)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(input.trim().to_string()))
+}
+
+/// Parses a duration like `90s`, `30m`, `24h`, `7d` or `2w`
+///
+/// A bare number is taken as seconds.
+fn parse_duration(input: &str) -> Result<Duration, String> {
+    let input = input.trim();
+    let split = input
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(input.len());
+    let (number, unit) = input.split_at(split);
+
+    let value: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration '{}': expected a number followed by s, m, h, d or w", input))?;
+    let multiplier = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(format!("invalid duration unit '{}': expected s, m, h, d or w", unit)),
+    };
+
+    value
+        .checked_mul(multiplier)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("duration '{}' is too large", input))
+}
+
+/// Parses a size like `512`, `500K`, `10M` or `1G` into bytes
+///
+/// Units are binary (1K = 1024 bytes) to match the summary output, are
+/// case-insensitive and may carry an optional trailing `B` (`10MB`).
+fn parse_size(input: &str) -> Result<u64, String> {
+    let input = input.trim();
+    let split = input
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(input.len());
+    let (number, unit) = input.split_at(split);
+
+    let value: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid size '{}': expected a number followed by K, M, G or T", input))?;
+    let unit = unit.to_ascii_uppercase();
+    let multiplier: u64 = match unit.trim_end_matches('B') {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return Err(format!("invalid size unit '{}': expected K, M, G or T", unit)),
+    };
+
+    value
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("size '{}' is too large", input))
+}
+
+/// Formats a count with thousands separators, e.g. `12,483`
+fn format_count(count: u64) -> String {
+    let digits = count.to_string();
+    let mut formatted = String::with_capacity(digits.len() + digits.len() / 3);
+    for (index, digit) in digits.chars().enumerate() {
+        if index > 0 && (digits.len() - index).is_multiple_of(3) {
+            formatted.push(',');
+        }
+        formatted.push(digit);
+    }
+    formatted
 }
 
 /// Formats bytes into human-readable format
@@ -272,66 +978,803 @@ fn format_bytes(bytes: u64) -> String {
     }
 }
 
+/// Prints the `--total-only` report
+fn print_totals(stats: &DeletionStats, duration: Duration) {
+    println!("📏 Totals:");
+    println!("  Files: {}", stats.files_deleted().to_string().cyan());
+    println!("  Directories: {}", stats.dirs_deleted().to_string().cyan());
+    println!("  Size: {}", format_bytes(stats.bytes_freed()).cyan());
+    println!("  Time taken: {:.2}s", duration.as_secs_f64());
+    if stats.errors_encountered() > 0 {
+        println!("  Unreadable entries: {}", stats.errors_encountered().to_string().red());
+    }
+}
+
+/// Prints a running summary line to stderr every `every` until aborted
+///
+/// Reads the shared counters directly, so it works without a TTY and
+/// alongside any other progress output.
+fn spawn_summary_ticker(stats: Arc<DeletionStats>, every: Duration) -> tokio::task::JoinHandle<()> {
+    let started = Instant::now();
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(every);
+        // The first tick completes immediately, before anything was deleted
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let files = stats.files_deleted();
+            eprintln!(
+                "[{}] {} files, {} so far ({:.0} files/sec)",
+                humantime::format_rfc3339_seconds(std::time::SystemTime::now()),
+                format_count(files),
+                format_bytes(stats.bytes_freed()),
+                files as f64 / started.elapsed().as_secs_f64()
+            );
+        }
+    })
+}
+
+/// Builds the `--measure-only-errors` exit code from the categorized counters
+fn error_bitmask(stats: &DeletionStats, failed_paths: u64) -> i32 {
+    let mut code = if failed_paths > 0 { EXIT_FAILURE } else { 0 };
+    for &(category, bit) in ERROR_BITS {
+        if stats.errors_in(category) > 0 {
+            code |= bit;
+        }
+    }
+    if stats.verification_failures() > 0 {
+        code |= UNVERIFIED_BIT;
+    }
+    code
+}
+
+/// How the run went at the target level, beyond what the engine's stats record
+struct RunOutcome {
+    /// Targets that could not be resolved or deleted at all
+    failed_paths: u64,
+    /// Targets skipped by `--ignore-missing`
+    missing_paths: u64,
+    /// Stopped by Ctrl-C
+    interrupted: bool,
+    /// Stopped early because `--min-free` was reached
+    min_free_reached: bool,
+    /// Files listed in the `--resume` manifest that no longer existed
+    already_gone: Option<u64>,
+}
+
+/// Queries free space once per filesystem holding the targets
+///
+/// Each filesystem is probed through a target's parent, which survives the
+/// deletion of the target itself. Filesystems that can't be queried are left
+/// out with a warning.
+async fn measure_disks(targets: &[PathBuf]) -> Vec<(PathBuf, FreeSpace)> {
+    let mut disks: Vec<(PathBuf, FreeSpace)> = Vec::new();
+    for target in targets {
+        let probe = target.parent().unwrap_or(target).to_path_buf();
+        match free_space(&probe).await {
+            Ok(space) if space.device.is_some() && disks.iter().any(|(_, known)| known.device == space.device) => {}
+            Ok(space) => disks.push((probe, space)),
+            Err(e) => eprintln!("{}", format!("⚠️  Can't query free space for {}: {}", escape_path(&probe), e).yellow()),
+        }
+    }
+    disks
+}
+
+/// Prints free space before and after the run for each measured filesystem
+async fn print_disk_change(before: &[(PathBuf, FreeSpace)]) {
+    println!();
+    for (probe, space) in before {
+        let after = match free_space(probe).await {
+            Ok(after) => format_bytes(after.available).green(),
+            Err(_) => "unknown".yellow(),
+        };
+        let label = if before.len() > 1 { format!(" on {}", escape_path(probe)) } else { String::new() };
+        println!("💽 Disk free{}: {} → {}", label, format_bytes(space.available), after);
+    }
+}
+
+/// Prints the human-readable completion summary
+fn print_summary(args: &Args, stats: &DeletionStats, duration: Duration, outcome: &RunOutcome) {
+    let (files, dirs, errors, bytes, skipped) = stats.get_summary();
+    let mounts_skipped = stats.mounts_skipped();
+    let RunOutcome { failed_paths, missing_paths, .. } = *outcome;
+
+    println!();
+    if outcome.interrupted {
+        println!("{}", "⚠️  Interrupted - stopped before finishing".yellow().bold());
+    } else if outcome.min_free_reached {
+        let verb = if args.dry_run { "would be" } else { "was" };
+        println!(
+            "{}",
+            format!("🎯 Enough space {} freed - stopped early, remaining entries were left in place", verb)
+                .green()
+                .bold()
+        );
+    } else if args.dry_run {
+        println!("{}", "🔍 DRY RUN - nothing was deleted".yellow().bold());
+    } else if failed_paths == 0 && args.trash {
+        println!("{}", "🗑️  Moved to trash successfully!".green().bold());
+    } else if failed_paths == 0 && args.backup.is_some() {
+        println!("{}", "📦 Moved to backup successfully!".green().bold());
+    } else if failed_paths == 0 {
+        println!("{}", "✅ Deletion completed successfully!".green().bold());
+    } else {
+        println!("{}", "❌ Deletion failed for some paths!".red().bold());
+    }
+    println!();
+    // Nothing is stat'ed in the unsafe fast mode, so there is no byte count to show
+    let freed = if args.assume_empty_fast_delete { "not measured".dimmed() } else { format_bytes(bytes).cyan() };
+    println!("📊 Summary:");
+    if args.dry_run {
+        println!("  Files that would be deleted: {}", files.to_string().cyan());
+        println!("  Directories that would be deleted: {}", dirs.to_string().cyan());
+        println!("  Space that would be freed: {}", freed);
+    } else {
+        println!("  Files deleted: {}", files.to_string().cyan());
+        println!("  Directories deleted: {}", dirs.to_string().cyan());
+        println!("  Space freed: {}", freed);
+    }
+    println!("  Time taken: {:.2}s", duration.as_secs_f64());
+    
+    if errors > 0 {
+        println!("  Errors encountered: {}", errors.to_string().red());
+        for (category, hint) in ERROR_HINTS {
+            let count = stats.errors_in(*category);
+            if count > 0 {
+                println!("    {}: {} {}", category.label(), count.to_string().red(), hint.dimmed());
+            }
+        }
+    }
+
+    if failed_paths > 0 {
+        println!("  Paths failed: {}", failed_paths.to_string().red());
+    }
+
+    if let Some(already_gone) = outcome.already_gone {
+        println!("  Already gone before resuming: {}", already_gone.to_string().cyan());
+    }
+    if missing_paths > 0 {
+        println!("  Missing paths skipped: {}", missing_paths.to_string().yellow());
+    }
+
+    let (backup_renames, backup_copies) = (stats.backup_renames(), stats.backup_copies());
+    if backup_renames > 0 {
+        println!("  Moved to backup (fast rename): {}", backup_renames.to_string().cyan());
+    }
+    if backup_copies > 0 {
+        println!("  Copied to backup (other filesystem): {}", backup_copies.to_string().yellow());
+    }
+
+    let trash_fallbacks = stats.trash_fallbacks();
+    if trash_fallbacks > 0 {
+        println!("  Moved to trash after delete failed: {}", trash_fallbacks.to_string().yellow());
+    }
+
+    let survivors = stats.survivors();
+    if !survivors.is_empty() {
+        println!("  Verification failures: {}", survivors.len().to_string().red());
+        for path in &survivors {
+            println!("    still present: {}", escape_path(path));
+        }
+    }
+
+    if skipped > 0 {
+        println!("  Entries skipped: {}", skipped.to_string().yellow());
+    }
+
+    if mounts_skipped > 0 {
+        println!("  Mount points skipped: {}", mounts_skipped.to_string().yellow());
+    }
+
+    let symlinks_skipped = stats.symlinks_skipped();
+    if symlinks_skipped > 0 {
+        println!("  Symlinks skipped: {}", symlinks_skipped.to_string().yellow());
+    }
+
+    if files > 0 {
+        let files_per_sec = files as f64 / duration.as_secs_f64();
+        println!("  Performance: {:.0} files/sec", files_per_sec);
+    }
+    if args.verbose > 0 {
+        let jobs = args.jobs.unwrap_or(DeleteOptions::default().jobs);
+        println!(
+            "  Peak concurrency: {} of {} jobs, {} filesystem calls",
+            stats.peak_concurrency().to_string().cyan(),
+            jobs,
+            format_count(stats.syscalls())
+        );
+    }
+
+    if args.keep_root {
+        println!("  Target root: {}", "preserved (--keep-root)".cyan());
+    }
+
+    let placeholders = stats.placeholders_created();
+    if let (Some(name), true) = (&args.keep_file, placeholders > 0) {
+        println!("  Placeholders created: {} ({})", placeholders.to_string().cyan(), name);
+    }
+
+    if let Some(min_free) = args.min_free {
+        let status = if outcome.min_free_reached { "reached" } else { "not reached, everything eligible was deleted" };
+        println!("  Space target: {} ({})", format_bytes(min_free).cyan(), status);
+    }
+
+    let breakdown = stats.breakdown();
+    if args.stats_by_dir && !breakdown.is_empty() {
+        println!();
+        println!("📁 By directory:");
+        println!("  {:>12}  {:>10}  {:>8}  Path", "Space", "Files", "Time");
+        for entry in &breakdown {
+            println!(
+                "  {:>12}  {:>10}  {:>7.2}s  {}",
+                format_bytes(entry.bytes),
+                entry.files,
+                entry.elapsed.as_secs_f64(),
+                escape_path(&entry.path)
+            );
+        }
+    }
+
+    let slowest = stats.slowest_directories(PROFILE_TOP_N);
+    if args.profile && !slowest.is_empty() {
+        println!();
+        println!("⏱️  Slowest directories:");
+        println!("  {:>8}  {:>10}  {:>12}  Path", "Time", "Files", "Space");
+        for entry in &slowest {
+            println!(
+                "  {:>7.2}s  {:>10}  {:>12}  {}",
+                entry.elapsed.as_secs_f64(),
+                entry.files,
+                format_bytes(entry.bytes),
+                escape_path(&entry.path)
+            );
+        }
+    }
+
+    let empty_dirs = stats.empty_dirs();
+    if args.report_empty && !empty_dirs.is_empty() {
+        println!();
+        println!("📭 Already empty directories ({}):", empty_dirs.len());
+        for path in &empty_dirs {
+            println!("  {}", escape_path(path));
+        }
+    }
+
+    let special_files = stats.special_files();
+    if !special_files.is_empty() {
+        println!();
+        let verb = if args.dry_run { "would be removed" } else { "removed" };
+        println!(
+            "{}",
+            format!("⚠️  Files with extended attributes or ACLs {} ({}):", verb, special_files.len()).yellow()
+        );
+        for path in &special_files {
+            println!("  {}", escape_path(path));
+        }
+    }
+
+    let kept_roots = stats.kept_roots();
+    if !kept_roots.is_empty() {
+        println!();
+        for (path, reason) in &kept_roots {
+            println!(
+                "{}",
+                format!(
+                    "ℹ️  Emptied but kept {}: it is {} and can't be removed (use --keep-root to skip this notice)",
+                    escape_path(path),
+                    reason
+                )
+                .yellow()
+            );
+        }
+    }
+
+    let special_kept = stats.special_kept();
+    if !special_kept.is_empty() {
+        println!();
+        println!(
+            "{}",
+            format!("🔒 FIFOs, sockets and device nodes left in place ({}):", special_kept.len()).yellow()
+        );
+        for path in &special_kept {
+            println!("  {}", escape_path(path));
+        }
+    }
+
+    let failures = stats.failures();
+    if args.list_errors && !failures.is_empty() {
+        println!();
+        println!("❌ Failed entries:");
+        for (path, error) in &failures {
+            println!("  [{}] {}: {}", ErrorCategory::of(error).label(), escape_path(path), error);
+        }
+    }
+
+    if skipped > 0 || mounts_skipped > 0 || symlinks_skipped > 0 || !special_kept.is_empty() {
+        println!();
+        println!(
+            "{}",
+            "Note: some entries were kept, so their parent directories (including the target root) were not removed."
+                .yellow()
+        );
+    }
+}
+
+/// Installs the console subscriber for the engine's `tracing` events
+///
+/// Nothing is shown without `--verbose`; `-v` shows removed directories and
+/// failures, `-vv` also every deleted file, and `-vvv` adds skipped and kept
+/// entries, timings and other detail. `RUST_LOG` overrides this.
+fn init_tracing(verbose: u8) {
+    // Warnings stay on even without -v so failures still reach stderr under --quiet
+    let level = match verbose {
+        0 => "warn",
+        1 => "info",
+        2 => "debug",
+        _ => "trace",
+    };
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(format!("fastdel={}", level)));
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(io::stderr)
+        .event_format(DimmedMessage)
+        .init();
+}
+
+/// Prints just the event message, dimmed, like the pre-`tracing` verbose output
+struct DimmedMessage;
+
+impl<S, N> FormatEvent<S, N> for DimmedMessage
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(&self, ctx: &FmtContext<'_, S, N>, mut writer: format::Writer<'_>, event: &Event<'_>) -> fmt::Result {
+        let mut message = String::new();
+        ctx.format_fields(format::Writer::new(&mut message), event)?;
+        writeln!(writer, "{}", message.dimmed())
+    }
+}
+
 /// Main application entry point
 #[tokio::main]
 async fn main() -> Result<()> {
-    let args = Args::parse();
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    match args.command {
+        Some(Command::Bench(bench_args)) => {
+            args.color.apply();
+            return bench::run(bench_args).await;
+        }
+        Some(Command::Completions { shell }) => {
+            let mut command = Args::command();
+            let name = command.get_name().to_string();
+            clap_complete::generate(shell, &mut command, name, &mut io::stdout());
+            return Ok(());
+        }
+        None => {}
+    }
+    // Defaults from the config file only fill in what the command line left out
+    let config = if args.no_config { None } else { config::Config::load()? };
+    let config_path = match config {
+        Some((path, config)) => {
+            config.apply(&mut args, &matches)?;
+            Some(path)
+        }
+        None => None,
+    };
+    args.color.apply();
+    init_tracing(args.verbose);
+    if let Some(path) = &config_path {
+        tracing::trace!("Using config file: {}", escape_path(path));
+    }
+    // Counting is a dry run with a shorter report
+    args.dry_run |= args.total_only;
+    for path in &args.exclude_from {
+        args.exclude.extend(read_pattern_file(path)?);
+    }
 
-    // Convert to absolute path to handle long Windows paths
-    let target_path = args.path.canonicalize()
-        .with_context(|| format!("Failed to resolve path: {}", args.path.display()))?;
+    let defaults = DeleteOptions::default();
+    let options = DeleteOptions {
+        verbose: args.verbose > 0,
+        on_progress: None,
+        jobs: args.jobs.unwrap_or(defaults.jobs),
+        max_open_dirs: args.max_open_dirs,
+        simple_max_entries: if args.simple { u64::MAX } else { args.simple_max_entries },
+        dry_run: args.dry_run,
+        trash: args.trash,
+        trash_on_error: args.trash_on_error,
+        excludes: args.exclude.clone(),
+        includes: args.include.clone(),
+        path_filter: args.path_filter.clone(),
+        path_filter_invert: args.path_filter_invert,
+        max_depth: args.max_depth,
+        keep_root: args.keep_root,
+        keep_file: args.keep_file.clone(),
+        report_empty: args.report_empty,
+        warn_special: args.warn_special,
+        skip_special: args.skip_special,
+        on_symlink: args.symlink_policy(),
+        unlink_root_symlink: args.no_canonicalize,
+        dereference_root: args.dereference_root_only,
+        assume_empty_fast_delete: args.assume_empty_fast_delete,
+        one_file_system: args.one_file_system,
+        progress: args.progress,
+        progress_template: args.progress_template.clone(),
+        retries: args.retries,
+        force: args.force,
+        older_than: args.older_than,
+        newer_than: args.newer_than,
+        larger_than: args.larger_than,
+        smaller_than: args.smaller_than,
+        exclude_larger_than: args.exclude_larger_than,
+        min_free: args.min_free,
+        stats_by_dir: args.stats_by_dir,
+        profile: args.profile,
+        gitignore: args.gitignore,
+        empty_dirs_only: args.empty_dirs_only,
+        progress_stream: args.progress_stream,
+        interactive: args.interactive,
+        log_file: args.log_file.clone(),
+        fail_fast: args.fail_fast,
+        largest_first: args.largest_first,
+        max_rate: args.max_rate,
+        concurrency_model: args.concurrency_model.into(),
+        delete_order: args.delete_order.into(),
+        blocking: args.blocking,
+        backup: args.backup.clone(),
+        verify: args.verify,
+    };
 
-    // Confirm deletion unless --yes flag is provided
-    if !args.yes && !confirm_deletion(&target_path)? {
-        println!("{}", "Deletion cancelled.".yellow());
-        return Ok(());
+    // Build the engine up front so invalid options fail before anything is touched
+    let engine = Arc::new(DeletionEngine::new(options)?);
+
+    // Gather targets from the command line, then the path list file and stdin
+    let mut requested = args.paths.clone();
+    if let Some(ref list) = args.from_file {
+        let file = std::fs::File::open(list)
+            .with_context(|| format!("Failed to open path list: {}", escape_path(list)))?;
+        requested.extend(read_path_list(io::BufReader::new(file))?);
+    }
+    if args.stdin {
+        if !args.yes && !args.dry_run {
+            anyhow::bail!("--stdin needs --yes or --dry-run, since stdin can't also answer the confirmation prompt");
+        }
+        requested.extend(read_path_list(io::stdin().lock())?);
+    }
+    if requested.is_empty() && args.resume.is_none() {
+        eprintln!("{}", "❌ No paths to delete".red());
+        std::process::exit(EXIT_FAILURE);
     }
 
-    println!("{}", "🚀 Starting fast deletion...".green().bold());
-    println!("Target: {}", target_path.display());
-    println!();
+    // Convert to absolute paths to handle long Windows paths, reporting and
+    // skipping any that can't be resolved so the rest still get deleted
+    let mut failed_paths: u64 = 0;
+    let mut missing_paths: u64 = 0;
+    let mut target_paths = Vec::with_capacity(requested.len());
+    for path in &requested {
+        let path = &match expand_path(path) {
+            Ok(expanded) => expanded,
+            Err(e) => {
+                failed_paths += 1;
+                eprintln!("{}", format!("❌ {}", e).red());
+                continue;
+            }
+        };
 
-    let start_time = Instant::now();
-    
-    // Create and run the deletion engine
-    let engine = DeletionEngine::new(args.verbose);
-    
-    match engine.delete_directory(&target_path).await {
-        Ok(()) => {
-            let duration = start_time.elapsed();
-            let stats = engine.get_stats();
-            let (files, dirs, errors, bytes) = stats.get_summary();
-
-            // Finish progress bar if it exists
-            if let Some(ref pb) = engine.progress_bar {
-                pb.finish_with_message("Deletion completed!");
+        // Canonicalizing `file.txt/` fails with a cryptic OS error, if at all
+        if let Err(e) = refuse_file_as_directory(path).await {
+            failed_paths += 1;
+            eprintln!("{}", format!("❌ {}", e).red());
+            continue;
+        }
+
+        // Canonicalizing resolves symlinks, so a linked root has to be caught
+        // first; without canonicalizing the link itself is the target, and
+        // with --dereference-root-only the resolved directory is
+        if args.symlink_policy() != SymlinkPolicy::Follow && !args.no_canonicalize && !args.dereference_root_only {
+            if let Err(e) = refuse_symlink_root(path).await {
+                failed_paths += 1;
+                eprintln!("{}", format!("❌ {}", e).red());
+                continue;
             }
+        }
 
-            // Print completion summary
-            println!();
-            println!("{}", "✅ Deletion completed successfully!".green().bold());
-            println!();
-            println!("📊 Summary:");
-            println!("  Files deleted: {}", files.to_string().cyan());
-            println!("  Directories deleted: {}", dirs.to_string().cyan());
-            println!("  Space freed: {}", format_bytes(bytes).cyan());
-            println!("  Time taken: {:.2}s", duration.as_secs_f64());
-            
-            if errors > 0 {
-                println!("  Errors encountered: {}", errors.to_string().red());
+        let resolved = if args.no_canonicalize {
+            absolute_lexical(path).and_then(|absolute| std::fs::symlink_metadata(&absolute).map(|_| absolute))
+        } else {
+            path.canonicalize()
+        };
+        match resolved {
+            Ok(resolved) => target_paths.push(resolved),
+            Err(e) if args.ignore_missing && e.kind() == io::ErrorKind::NotFound => {
+                missing_paths += 1;
+                if !args.quiet {
+                    eprintln!("{}", format!("⚠️  Skipping missing path: {}", escape_path(path)).yellow());
+                }
+            }
+            Err(e) => {
+                failed_paths += 1;
+                eprintln!("{}", format!("❌ Failed to resolve path: {}", escape_path(path)).red());
+                eprintln!("Error: {}", e);
             }
+        }
+    }
 
-            if files > 0 {
-                let files_per_sec = files as f64 / duration.as_secs_f64();
-                println!("  Performance: {:.0} files/sec", files_per_sec);
+    if args.fail_fast && failed_paths > 0 {
+        std::process::exit(EXIT_FAILURE);
+    }
+    if target_paths.is_empty() && args.resume.is_none() {
+        // Nothing left to do is success when every target was already gone
+        std::process::exit(if failed_paths == 0 { 0 } else { EXIT_FAILURE });
+    }
+
+    // Never wipe a whole filesystem or a system directory by accident
+    if !args.force && !args.dry_run {
+        let protected = protected_dirs(args.protected.as_deref());
+        let refused = target_paths.iter().find_map(|path| {
+            protected_reason(path, &protected, args.protected.is_some()).map(|reason| (path, reason))
+        });
+        if let Some((target, reason)) = refused {
+            anyhow::bail!(
+                "Refusing to delete {} {} (use --force to override)",
+                reason,
+                escape_path(target)
+            );
+        }
+        if let Ok(cwd) = std::env::current_dir() {
+            if let Some(target) = target_paths.iter().find(|path| contains_dir(path, &cwd)) {
+                anyhow::bail!(
+                    "Refusing to delete {}, which contains the current directory {}; cd elsewhere first (or use --force)",
+                    escape_path(target),
+                    escape_path(&cwd)
+                );
             }
         }
-        Err(e) => {
-            println!("{}", "❌ Deletion failed!".red().bold());
-            println!("Error: {}", e);
-            std::process::exit(1);
+    }
+
+    if (args.preview.is_some() || args.preview_all) && !args.quiet {
+        let mut out: Box<dyn Write> = if args.json {
+            Box::new(io::stderr())
+        } else {
+            Box::new(io::stdout())
+        };
+        let (depth, width) = if args.preview_all { (None, None) } else { (args.preview, Some(PREVIEW_WIDTH)) };
+        for path in &target_paths {
+            print_preview(&mut out, &preview_tree(path, depth, width).await, "", "")?;
         }
+        writeln!(out)?;
+    }
+
+    // Confirm deletion unless --yes flag is provided; a dry run destroys nothing.
+    // Small deletions may skip the prompt, but dangerous targets never do
+    let below_threshold = match args.no_confirm_under {
+        Some(threshold) if !args.yes && !args.dry_run && args.resume.is_none() => {
+            !target_paths.iter().any(|path| danger_reason(path, args.confirm_depth).is_some())
+                && threshold.allows(&target_paths).await
+        }
+        _ => false,
+    };
+    if !args.yes && !args.dry_run && !below_threshold {
+        // A resumed run lists the manifest, whose targets were confirmed the first time
+        let (confirm_paths, confirm_depth) = match args.resume {
+            Some(ref manifest) => (std::slice::from_ref(manifest), 0),
+            None => (target_paths.as_slice(), args.confirm_depth),
+        };
+        let mut prompt_out: Box<dyn Write> = if args.json {
+            Box::new(io::stderr())
+        } else {
+            Box::new(io::stdout())
+        };
+        // Optional pre-scan so the prompt can say how much is at stake
+        let totals = if args.count_before && args.resume.is_none() {
+            let mut totals = Vec::with_capacity(target_paths.len());
+            for path in &target_paths {
+                totals.push(scan_tree(path).await);
+            }
+            Some(totals)
+        } else {
+            None
+        };
+        if !confirm_deletion(
+            &mut prompt_out,
+            confirm_paths,
+            totals.as_deref(),
+            if args.resume.is_some() {
+                "delete the remaining files listed in"
+            } else if args.backup.is_some() {
+                "move to backup"
+            } else if args.trash {
+                "move to trash"
+            } else {
+                "permanently delete"
+            },
+            confirm_depth,
+            args.default_yes,
+        )? {
+            writeln!(prompt_out, "{}", "Deletion cancelled.".yellow())?;
+            return Ok(());
+        }
+    }
+
+    // Snapshot everything before the first deletion, somewhere it won't be deleted
+    if let Some(ref manifest) = args.manifest {
+        let resolved = manifest
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or(Path::new("."))
+            .canonicalize()
+            .map(|parent| parent.join(manifest.file_name().unwrap_or_default()));
+        if let Ok(resolved) = resolved {
+            if let Some(target) = target_paths.iter().find(|target| resolved.starts_with(target)) {
+                anyhow::bail!(
+                    "Refusing to write the manifest inside {}, which is about to be deleted",
+                    escape_path(target)
+                );
+            }
+        }
+
+        let listed = if args.checksum_manifest {
+            let jobs = args.jobs.unwrap_or(DeleteOptions::default().jobs);
+            write_checksum_manifest(&target_paths, manifest, jobs).await?
+        } else {
+            write_manifest(&target_paths, manifest).await?
+        };
+        if !args.json && !args.quiet {
+            println!("📝 Wrote manifest of {} files to {}", listed, escape_path(manifest));
+        }
+    }
+
+    if !args.json && !args.quiet && !args.total_only {
+        if args.dry_run {
+            println!("{}", "🔍 Starting dry run...".green().bold());
+        } else if args.trash {
+            println!("{}", "🗑️  Moving to trash...".green().bold());
+        } else if let Some(backup_dir) = engine.backup_dir() {
+            println!("{}", format!("📦 Moving to backup in {}...", escape_path(backup_dir)).green().bold());
+        } else {
+            println!("{}", "🚀 Starting fast deletion...".green().bold());
+        }
+        for target_path in &target_paths {
+            println!("Target: {}", escape_path(target_path));
+        }
+        if let Some(ref manifest) = args.resume {
+            println!("Resuming from manifest: {}", escape_path(manifest));
+        }
+        if args.assume_empty_fast_delete {
+            println!("{}", "⚠️  Unsafe fast mode: entries aren't stat'ed and sizes aren't measured".yellow());
+        }
+        println!();
+    }
+
+    // The first Ctrl-C lets in-flight deletions finish so the partial summary
+    // is accurate; a second one exits immediately
+    let interrupt_engine = Arc::clone(&engine);
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            interrupt_engine.cancel();
+            eprintln!("{}", "\n⚠️  Interrupted, waiting for in-flight deletions (Ctrl-C again to abort)...".yellow());
+            if tokio::signal::ctrl_c().await.is_ok() {
+                std::process::exit(EXIT_INTERRUPTED);
+            }
+        }
+    });
+
+    let disk_before = if args.show_disk { measure_disks(&target_paths).await } else { Vec::new() };
+    let start_time = Instant::now();
+    let summary_ticker = args
+        .summary_interval
+        .map(|secs| spawn_summary_ticker(engine.get_stats(), Duration::from_secs(secs)));
+    
+    // Run the deletion engine, sharing its stats across all targets
+    let mut already_gone = None;
+    if let Some(ref manifest) = args.resume {
+        match engine.resume_from_manifest(manifest).await {
+            Ok(gone) => already_gone = Some(gone),
+            Err(e) => {
+                failed_paths += 1;
+                eprintln!("{}", format!("❌ Resume failed: {}", escape_path(manifest)).red().bold());
+                eprintln!("Error: {}", e);
+            }
+        }
+    } else if args.parallel_roots {
+        // Each target is its own task; the engine's job limit is shared by all of them
+        let mut deletions = tokio::task::JoinSet::new();
+        for target_path in target_paths.clone() {
+            let engine = Arc::clone(&engine);
+            deletions.spawn(async move {
+                let result = engine.delete_directory(&target_path).await;
+                (target_path, result)
+            });
+        }
+        while let Some(joined) = deletions.join_next().await {
+            let (target_path, result) = joined.context("Deletion task failed")?;
+            if let Err(e) = result {
+                failed_paths += 1;
+                eprintln!("{}", format!("❌ Deletion failed: {}", escape_path(&target_path)).red().bold());
+                eprintln!("Error: {}", e);
+                if args.fail_fast {
+                    deletions.abort_all();
+                    break;
+                }
+            }
+        }
+    } else {
+        for target_path in &target_paths {
+            if engine.is_cancelled() {
+                break;
+            }
+            if let Err(e) = engine.delete_directory(target_path).await {
+                failed_paths += 1;
+                eprintln!("{}", format!("❌ Deletion failed: {}", escape_path(target_path)).red().bold());
+                eprintln!("Error: {}", e);
+                if args.fail_fast {
+                    break;
+                }
+            }
+        }
+    }
+
+    if let Some(summary_ticker) = summary_ticker {
+        summary_ticker.abort();
+    }
+    let duration = start_time.elapsed();
+    let stats = engine.get_stats();
+
+    // Finish progress bar if it exists
+    let min_free_reached = engine.min_free_reached();
+    let interrupted = engine.is_cancelled() && !min_free_reached;
+    engine.finish_progress(if interrupted { "Interrupted" } else { "Deletion completed!" });
+    engine.flush_log().await?;
+
+    let summary = DeletionSummary::new(&stats, duration);
+    if args.json {
+        println!("{}", serde_json::to_string(&summary)?);
+    } else if args.total_only && !args.quiet {
+        print_totals(&stats, duration);
+    } else if !args.quiet {
+        let outcome = RunOutcome {
+            failed_paths,
+            missing_paths,
+            interrupted,
+            min_free_reached,
+            already_gone,
+        };
+        print_summary(&args, &stats, duration, &outcome);
+    }
+    if !args.json && !args.quiet && !disk_before.is_empty() {
+        print_disk_change(&disk_before).await;
+    }
+    // Written even after failures, so the file always reflects the work done
+    if let Some(ref path) = args.summary_json {
+        let json = serde_json::to_string(&summary)?;
+        std::fs::write(path, json + "\n")
+            .with_context(|| format!("Failed to write summary to {}", escape_path(path)))?;
+    }
+
+    if interrupted {
+        std::process::exit(EXIT_INTERRUPTED);
+    }
+
+    if args.measure_only_errors {
+        let code = error_bitmask(&stats, failed_paths);
+        if code != 0 {
+            std::process::exit(code);
+        }
+        return Ok(());
+    }
+
+    if failed_paths > 0 {
+        std::process::exit(EXIT_FAILURE);
+    }
+
+    if stats.errors_encountered() > 0 {
+        std::process::exit(EXIT_PARTIAL);
+    }
+
+    if stats.verification_failures() > 0 {
+        std::process::exit(EXIT_UNVERIFIED);
     }
 
     Ok(())
-}
\ No newline at end of file
+}
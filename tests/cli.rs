@@ -0,0 +1,305 @@
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+#[cfg(unix)]
+mod common;
+#[cfg(unix)]
+use common::{pin, unpin};
+
+/// Runs the `fastdel` binary from `dir`, ignoring any user config file
+fn fastdel(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_fastdel"))
+        .current_dir(dir)
+        .env("XDG_CONFIG_HOME", dir)
+        .env("NO_COLOR", "1")
+        .env_remove("RUST_LOG")
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[cfg(unix)]
+#[test]
+fn quiet_still_prints_errors_to_stderr() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    let locked = target.join("locked");
+    fs::create_dir_all(&locked).unwrap();
+    fs::write(target.join("loose.txt"), b"data").unwrap();
+    fs::write(locked.join("stuck.txt"), b"data").unwrap();
+    if !pin(&locked.join("stuck.txt")) {
+        unpin(&locked.join("stuck.txt"));
+        return;
+    }
+
+    let output = fastdel(sandbox.path(), &["--quiet", "--yes", "target"]);
+    unpin(&locked.join("stuck.txt"));
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.stdout.is_empty(), "{}", String::from_utf8_lossy(&output.stdout));
+    assert!(stderr.contains("stuck.txt"), "{}", stderr);
+    assert!(!output.status.success());
+    assert!(!target.join("loose.txt").exists());
+}
+
+#[test]
+fn zero_jobs_is_rejected_before_deleting_anything() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    fs::create_dir_all(&target).unwrap();
+    fs::write(target.join("file.txt"), b"data").unwrap();
+
+    let output = fastdel(sandbox.path(), &["--jobs", "0", "--yes", "target"]);
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr);
+    assert!(stderr.contains("--jobs must be at least 1"), "{}", stderr);
+    assert!(target.join("file.txt").exists());
+}
+
+#[test]
+fn several_targets_are_deleted_with_one_combined_summary() {
+    let sandbox = tempfile::tempdir().unwrap();
+    for (name, files) in [("first", 1), ("second", 2)] {
+        let target = sandbox.path().join(name);
+        fs::create_dir_all(&target).unwrap();
+        for file in 0..files {
+            fs::write(target.join(format!("{}.txt", file)), b"data").unwrap();
+        }
+    }
+
+    let output = fastdel(sandbox.path(), &["--yes", "first", "second"]);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(!sandbox.path().join("first").exists());
+    assert!(!sandbox.path().join("second").exists());
+    assert!(stdout.contains("Files deleted: 3"), "{}", stdout);
+    assert!(stdout.contains("Directories deleted: 2"), "{}", stdout);
+}
+
+#[test]
+fn json_prints_only_a_parseable_summary_on_stdout() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    fs::create_dir_all(target.join("nested")).unwrap();
+    fs::write(target.join("a.txt"), b"a").unwrap();
+    fs::write(target.join("nested").join("b.txt"), b"b").unwrap();
+
+    let output = fastdel(sandbox.path(), &["--json", "--yes", "target"]);
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let summary: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(summary["files_deleted"], 2);
+    assert_eq!(summary["dirs_deleted"], 2);
+    assert_eq!(summary["errors"], 0);
+    assert!(summary["bytes_freed"].as_u64().unwrap() > 0);
+    assert!(!target.exists());
+}
+
+#[test]
+fn missing_target_exits_with_failure() {
+    let sandbox = tempfile::tempdir().unwrap();
+    fs::create_dir_all(sandbox.path().join("present")).unwrap();
+
+    let output = fastdel(sandbox.path(), &["--yes", "present", "missing"]);
+
+    assert_eq!(output.status.code(), Some(1), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(!sandbox.path().join("present").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn undeletable_entries_exit_with_partial_failure() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    let locked = target.join("locked");
+    fs::create_dir_all(&locked).unwrap();
+    fs::write(locked.join("stuck.txt"), b"data").unwrap();
+    if !pin(&locked.join("stuck.txt")) {
+        unpin(&locked.join("stuck.txt"));
+        return;
+    }
+
+    let output = fastdel(sandbox.path(), &["--yes", "target"]);
+    unpin(&locked.join("stuck.txt"));
+
+    assert_eq!(output.status.code(), Some(3), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(locked.join("stuck.txt").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn interrupt_stops_the_run_with_exit_code_130() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    fs::create_dir_all(&target).unwrap();
+    for file in 0..50 {
+        fs::write(target.join(format!("{}.txt", file)), b"data").unwrap();
+    }
+
+    // Throttled so the run is still going when the signal arrives
+    let mut child = Command::new(env!("CARGO_BIN_EXE_fastdel"))
+        .current_dir(sandbox.path())
+        .env("XDG_CONFIG_HOME", sandbox.path())
+        .args(["--yes", "--max-rate", "10", "target"])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+    while fs::read_dir(&target).unwrap().count() == 50 {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGINT) };
+    let status = child.wait().unwrap();
+
+    assert_eq!(status.code(), Some(130));
+    assert!(fs::read_dir(&target).unwrap().count() > 0);
+}
+
+#[test]
+fn targets_are_read_from_a_file_and_from_stdin() {
+    use std::io::Write as _;
+
+    let sandbox = tempfile::tempdir().unwrap();
+    for name in ["listed", "piped", "argument", "untouched"] {
+        fs::create_dir_all(sandbox.path().join(name)).unwrap();
+        fs::write(sandbox.path().join(name).join("file.txt"), b"data").unwrap();
+    }
+    fs::write(sandbox.path().join("targets.txt"), "\n  listed  \n\n").unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_fastdel"))
+        .current_dir(sandbox.path())
+        .env("XDG_CONFIG_HOME", sandbox.path())
+        .args(["--yes", "--stdin", "--from-file", "targets.txt", "argument"])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"piped\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(!sandbox.path().join("listed").exists());
+    assert!(!sandbox.path().join("piped").exists());
+    assert!(!sandbox.path().join("argument").exists());
+    assert!(sandbox.path().join("untouched").join("file.txt").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn trash_on_error_deletes_permanently_and_reports_what_the_trash_refused() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    let locked = target.join("locked");
+    fs::create_dir_all(&locked).unwrap();
+    fs::write(target.join("loose.txt"), b"data").unwrap();
+    fs::write(locked.join("stuck.txt"), b"data").unwrap();
+    if !pin(&locked.join("stuck.txt")) {
+        unpin(&locked.join("stuck.txt"));
+        return;
+    }
+
+    // An entry that can't be unlinked can't be moved into the trash either,
+    // so the fallback fails too and the entry is reported
+    let output = Command::new(env!("CARGO_BIN_EXE_fastdel"))
+        .current_dir(sandbox.path())
+        .env("XDG_CONFIG_HOME", sandbox.path())
+        .env("XDG_DATA_HOME", sandbox.path().join("data"))
+        .env("HOME", sandbox.path())
+        .args(["--yes", "--trash-on-error", "target"])
+        .output()
+        .unwrap();
+    unpin(&locked.join("stuck.txt"));
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(3), "{}", stderr);
+    assert!(stderr.contains("stuck.txt"), "{}", stderr);
+    assert!(locked.join("stuck.txt").exists());
+    assert!(!target.join("loose.txt").exists());
+    // Only entries that failed are offered to the trash
+    assert!(!sandbox.path().join("data").join("Trash").join("files").join("loose.txt").exists());
+}
+
+#[test]
+fn config_file_fills_in_options_the_command_line_leaves_out() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let build = |name: &str| {
+        let target = sandbox.path().join(name);
+        fs::create_dir_all(&target).unwrap();
+        for file in ["a.log", "b.tmp", "c.txt"] {
+            fs::write(target.join(file), b"data").unwrap();
+        }
+        target
+    };
+    let remaining = |target: &Path| {
+        let mut names: Vec<String> =
+            fs::read_dir(target).unwrap().map(|entry| entry.unwrap().file_name().into_string().unwrap()).collect();
+        names.sort();
+        names
+    };
+    // The local file wins over the one in XDG_CONFIG_HOME; they aren't merged
+    fs::create_dir_all(sandbox.path().join("fastdel")).unwrap();
+    fs::write(sandbox.path().join("fastdel").join("config.toml"), "exclude = [\"*.tmp\"]\n").unwrap();
+    fs::write(sandbox.path().join(".fastdel.toml"), "yes = true\nquiet = true\nexclude = [\"*.log\"]\n").unwrap();
+
+    let from_file = build("from_file");
+    let output = fastdel(sandbox.path(), &["from_file"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(output.stdout.is_empty(), "{}", String::from_utf8_lossy(&output.stdout));
+    assert_eq!(remaining(&from_file), ["a.log"]);
+
+    // A list on the command line replaces the configured one, and a flag
+    // clap would reject together with a configured switch turns it off
+    let overridden = build("overridden");
+    let output = fastdel(sandbox.path(), &["--exclude", "*.txt", "--verbose", "overridden"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(!output.stdout.is_empty());
+    assert_eq!(remaining(&overridden), ["c.txt"]);
+
+    let ignored = build("ignored");
+    let output = fastdel(sandbox.path(), &["--no-config", "--yes", "ignored"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(!ignored.exists());
+}
+
+#[test]
+fn protected_directories_are_refused_without_force() {
+    let sandbox = tempfile::tempdir().unwrap();
+    for name in ["home", "precious"] {
+        fs::create_dir_all(sandbox.path().join(name)).unwrap();
+        fs::write(sandbox.path().join(name).join("file.txt"), b"data").unwrap();
+    }
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_fastdel"))
+            .current_dir(sandbox.path())
+            .env("XDG_CONFIG_HOME", sandbox.path())
+            .env("HOME", sandbox.path().join("home"))
+            .env("NO_COLOR", "1")
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    let output = run(&["--yes", "home"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr);
+    assert!(stderr.contains("Refusing to delete your home directory"), "{}", stderr);
+    assert!(sandbox.path().join("home").join("file.txt").exists());
+
+    // A configured list replaces the built-in one, home directory included
+    let precious = sandbox.path().join("precious");
+    fs::write(sandbox.path().join(".fastdel.toml"), format!("protected = [{:?}]\n", precious)).unwrap();
+    let output = run(&["--yes", "precious"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr);
+    assert!(stderr.contains("Refusing to delete the protected system directory"), "{}", stderr);
+    assert!(precious.join("file.txt").exists());
+
+    let output = run(&["--yes", "--force", "precious", "home"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(!precious.exists());
+    assert!(!sandbox.path().join("home").exists());
+}
@@ -0,0 +1,39 @@
+//! Unix helpers shared by the integration tests
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::Command;
+
+/// Makes `file` impossible to unlink, returning false when that can't be arranged
+///
+/// Unprivileged users are stopped by a read-only parent; root needs the
+/// immutable attribute instead. Undo with [`unpin`].
+pub fn pin(file: &Path) -> bool {
+    let parent = file.parent().unwrap();
+    fs::set_permissions(parent, fs::Permissions::from_mode(0o555)).unwrap();
+    if fs::write(parent.join(".probe"), b"").is_err() {
+        return true;
+    }
+    fs::remove_file(parent.join(".probe")).unwrap();
+    fs::set_permissions(parent, fs::Permissions::from_mode(0o755)).unwrap();
+    Command::new("chattr").arg("+i").arg(file).output().is_ok_and(|output| output.status.success())
+}
+
+/// Makes `dir` read-only, returning false (and undoing it) when that doesn't
+/// stop this user from changing its entries, as for root
+#[allow(dead_code)] // Only the library tests lock directories
+pub fn lock_dir(dir: &Path) -> bool {
+    fs::set_permissions(dir, fs::Permissions::from_mode(0o555)).unwrap();
+    if fs::write(dir.join(".probe"), b"").is_err() {
+        return true;
+    }
+    fs::remove_file(dir.join(".probe")).unwrap();
+    fs::set_permissions(dir, fs::Permissions::from_mode(0o755)).unwrap();
+    false
+}
+
+pub fn unpin(file: &Path) {
+    let _ = Command::new("chattr").arg("-i").arg(file).output();
+    fs::set_permissions(file.parent().unwrap(), fs::Permissions::from_mode(0o755)).unwrap();
+}
@@ -0,0 +1,1554 @@
+use fastdel::{delete, DeleteOptions};
+use std::fs;
+use std::path::PathBuf;
+
+#[cfg(unix)]
+mod common;
+
+#[cfg(unix)]
+#[tokio::test]
+async fn symlinked_directory_is_unlinked_not_traversed() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    let sibling = sandbox.path().join("sibling");
+    fs::create_dir_all(&target).unwrap();
+    fs::create_dir_all(&sibling).unwrap();
+    fs::write(sibling.join("keep.txt"), b"keep").unwrap();
+    std::os::unix::fs::symlink(&sibling, target.join("link")).unwrap();
+
+    let stats = delete(&target, DeleteOptions::default()).await.unwrap();
+
+    assert!(!target.exists());
+    assert!(sibling.join("keep.txt").exists());
+    assert_eq!(stats.files_deleted(), 1);
+    assert_eq!(stats.errors_encountered(), 0);
+}
+
+#[cfg(windows)]
+#[tokio::test]
+async fn force_removes_read_only_file() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    fs::create_dir_all(&target).unwrap();
+    let file = target.join("readonly.txt");
+    fs::write(&file, b"locked").unwrap();
+    let mut permissions = fs::metadata(&file).unwrap().permissions();
+    permissions.set_readonly(true);
+    fs::set_permissions(&file, permissions).unwrap();
+
+    let stats = delete(&target, DeleteOptions::default()).await.unwrap();
+    assert!(file.exists());
+    assert_eq!(stats.errors_encountered(), 1);
+
+    let options = DeleteOptions {
+        force: true,
+        ..DeleteOptions::default()
+    };
+    let stats = delete(&target, options).await.unwrap();
+
+    assert!(!target.exists());
+    assert_eq!(stats.files_deleted(), 1);
+    assert_eq!(stats.errors_encountered(), 0);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn force_removes_files_from_a_read_only_directory() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    let locked = target.join("locked");
+    fs::create_dir_all(&locked).unwrap();
+    fs::write(locked.join("stuck.txt"), b"locked").unwrap();
+    if !common::lock_dir(&locked) {
+        return;
+    }
+
+    let stats = delete(&target, DeleteOptions::default()).await.unwrap();
+    assert!(locked.join("stuck.txt").exists());
+    assert_eq!(stats.errors_encountered(), 1);
+
+    let options = DeleteOptions {
+        force: true,
+        ..DeleteOptions::default()
+    };
+    let stats = delete(&target, options).await.unwrap();
+
+    assert!(!target.exists());
+    assert_eq!(stats.files_deleted(), 1);
+    assert_eq!(stats.errors_encountered(), 0);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn force_leaves_the_directory_holding_the_target_alone() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let sandbox = tempfile::tempdir().unwrap();
+    let outside = sandbox.path().join("outside");
+    let target = outside.join("target");
+    fs::create_dir_all(&target).unwrap();
+    fs::write(target.join("file.txt"), b"data").unwrap();
+    if !common::lock_dir(&outside) {
+        return;
+    }
+
+    let options = DeleteOptions {
+        force: true,
+        ..DeleteOptions::default()
+    };
+    let stats = delete(&target, options).await.unwrap();
+    let mode = fs::metadata(&outside).unwrap().permissions().mode();
+    fs::set_permissions(&outside, fs::Permissions::from_mode(0o755)).unwrap();
+
+    // The contents go, but the target itself is reported, not forced
+    assert_eq!(mode & 0o777, 0o555);
+    assert!(target.exists());
+    assert_eq!(stats.files_deleted(), 1);
+    assert_eq!(stats.errors_encountered(), 1);
+}
+
+#[tokio::test]
+async fn deep_tree_is_deleted_without_errors() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    for branch in 0..8 {
+        let mut dir = target.join(format!("branch{}", branch));
+        for level in 0..40 {
+            fs::create_dir_all(&dir).unwrap();
+            for file in 0..5 {
+                fs::write(dir.join(format!("file{}.txt", file)), b"data").unwrap();
+            }
+            dir = dir.join(format!("level{}", level));
+        }
+    }
+
+    let options = DeleteOptions {
+        jobs: 64,
+        ..DeleteOptions::default()
+    };
+    let stats = delete(&target, options).await.unwrap();
+
+    assert!(!target.exists());
+    assert_eq!(stats.files_deleted(), 8 * 40 * 5);
+    assert_eq!(stats.errors_encountered(), 0);
+}
+
+#[cfg(windows)]
+#[tokio::test]
+async fn path_longer_than_max_path_is_deleted() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    let mut dir = target.clone();
+    while dir.as_os_str().len() < 400 {
+        dir = dir.join("a_fairly_long_directory_name_for_nesting");
+    }
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("deep.txt"), b"deep").unwrap();
+
+    let stats = delete(&target, DeleteOptions::default()).await.unwrap();
+
+    assert!(!target.exists());
+    assert_eq!(stats.files_deleted(), 1);
+    assert_eq!(stats.errors_encountered(), 0);
+}
+
+#[cfg(windows)]
+#[tokio::test]
+async fn junction_is_removed_not_traversed() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("node_modules");
+    let sibling = sandbox.path().join("package");
+    fs::create_dir_all(&target).unwrap();
+    fs::create_dir_all(&sibling).unwrap();
+    fs::write(sibling.join("keep.txt"), b"keep").unwrap();
+    // Junctions need no special privileges, unlike directory symlinks
+    let status = std::process::Command::new("cmd")
+        .arg("/C")
+        .arg("mklink")
+        .arg("/J")
+        .arg(target.join("package"))
+        .arg(&sibling)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let stats = delete(&target, DeleteOptions::default()).await.unwrap();
+
+    assert!(!target.exists());
+    assert!(sibling.join("keep.txt").exists());
+    assert_eq!(stats.files_deleted(), 1);
+    assert_eq!(stats.errors_encountered(), 0);
+}
+
+#[tokio::test]
+async fn single_open_directory_limit_still_deletes_nested_tree() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    for branch in 0..4 {
+        let dir = target.join(format!("branch{}", branch)).join("nested").join("deeper");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("file.txt"), b"data").unwrap();
+    }
+
+    let options = DeleteOptions {
+        max_open_dirs: 1,
+        ..DeleteOptions::default()
+    };
+    let stats = delete(&target, options).await.unwrap();
+
+    assert!(!target.exists());
+    assert_eq!(stats.files_deleted(), 4);
+    assert_eq!(stats.errors_encountered(), 0);
+}
+
+#[tokio::test]
+async fn include_deletes_only_matches_and_exclude_wins() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    fs::create_dir_all(target.join("mixed")).unwrap();
+    fs::create_dir_all(target.join("only_tmp")).unwrap();
+    fs::create_dir_all(target.join("already_empty")).unwrap();
+    fs::create_dir_all(target.join("cache").join("nested")).unwrap();
+    fs::write(target.join("a.tmp"), b"a").unwrap();
+    fs::write(target.join("keep.tmp"), b"k").unwrap();
+    fs::write(target.join("b.txt"), b"b").unwrap();
+    fs::write(target.join("mixed").join("c.tmp"), b"c").unwrap();
+    fs::write(target.join("mixed").join("d.txt"), b"d").unwrap();
+    fs::write(target.join("only_tmp").join("e.tmp"), b"e").unwrap();
+    fs::write(target.join("cache").join("nested").join("f.txt"), b"f").unwrap();
+
+    let options = DeleteOptions {
+        includes: vec!["*.tmp".to_string(), "cache".to_string()],
+        excludes: vec!["keep.tmp".to_string()],
+        ..DeleteOptions::default()
+    };
+    let stats = delete(&target, options).await.unwrap();
+
+    assert!(!target.join("a.tmp").exists());
+    assert!(target.join("keep.tmp").exists());
+    assert!(target.join("b.txt").exists());
+    assert!(!target.join("mixed").join("c.tmp").exists());
+    assert!(target.join("mixed").join("d.txt").exists());
+    assert!(!target.join("only_tmp").exists());
+    assert!(target.join("already_empty").exists());
+    assert!(!target.join("cache").exists());
+    assert_eq!(stats.files_deleted(), 4);
+    assert_eq!(stats.errors_encountered(), 0);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn symlinked_root_is_refused() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let real = sandbox.path().join("real");
+    let link = sandbox.path().join("link");
+    fs::create_dir_all(&real).unwrap();
+    fs::write(real.join("important.txt"), b"keep").unwrap();
+    std::os::unix::fs::symlink(&real, &link).unwrap();
+
+    let error = delete(&link, DeleteOptions::default()).await.unwrap_err();
+
+    assert!(error.to_string().contains("symlink"));
+    assert!(real.join("important.txt").exists());
+    assert!(link.exists());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn sparse_file_counts_allocated_blocks_not_length() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    fs::create_dir_all(&target).unwrap();
+    let logical_size = 64 * 1024 * 1024;
+    let file = fs::File::create(target.join("disk.img")).unwrap();
+    file.set_len(logical_size).unwrap();
+    drop(file);
+
+    let stats = delete(&target, DeleteOptions::default()).await.unwrap();
+
+    assert!(!target.exists());
+    assert_eq!(stats.files_deleted(), 1);
+    assert!(stats.bytes_freed() < logical_size);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn unreadable_root_fails_with_a_single_error() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    fs::create_dir_all(&target).unwrap();
+    fs::write(target.join("file.txt"), b"data").unwrap();
+    fs::set_permissions(&target, fs::Permissions::from_mode(0o000)).unwrap();
+
+    // Privileged users can read the directory regardless of its mode bits
+    if fs::read_dir(&target).is_ok() {
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o755)).unwrap();
+        return;
+    }
+
+    let result = delete(&target, DeleteOptions::default()).await;
+    fs::set_permissions(&target, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let error = format!("{:#}", result.expect_err("an unreadable root should be an error"));
+    assert!(error.contains("Permission denied reading"), "{}", error);
+    assert!(target.join("file.txt").exists());
+}
+
+#[tokio::test]
+async fn max_rate_spaces_out_removals() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    fs::create_dir_all(&target).unwrap();
+    for i in 0..5 {
+        fs::write(target.join(format!("file{}.txt", i)), b"data").unwrap();
+    }
+
+    let options = DeleteOptions {
+        max_rate: Some(20),
+        ..DeleteOptions::default()
+    };
+    let started = std::time::Instant::now();
+    let stats = delete(&target, options).await.unwrap();
+
+    // The first removal starts right away, the other four wait 50ms each
+    assert!(started.elapsed() >= std::time::Duration::from_millis(200));
+    assert!(!target.exists());
+    assert_eq!(stats.files_deleted(), 5);
+}
+
+#[tokio::test]
+async fn backup_renames_target_into_timestamped_folder() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    let backup = sandbox.path().join("backup");
+    fs::create_dir_all(target.join("nested")).unwrap();
+    fs::write(target.join("nested").join("file.txt"), b"data").unwrap();
+
+    let options = DeleteOptions {
+        backup: Some(backup.clone()),
+        ..DeleteOptions::default()
+    };
+    let stats = delete(&target, options).await.unwrap();
+
+    assert!(!target.exists());
+    let run_dirs: Vec<_> = fs::read_dir(&backup).unwrap().map(|entry| entry.unwrap().path()).collect();
+    assert_eq!(run_dirs.len(), 1);
+    assert!(run_dirs[0].join("target").join("nested").join("file.txt").exists());
+    assert_eq!(stats.backup_renames(), 1);
+    assert_eq!(stats.files_deleted(), 1);
+}
+
+#[tokio::test]
+async fn merge_from_sums_stats_across_runs() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let first = sandbox.path().join("first");
+    let second = sandbox.path().join("second");
+    fs::create_dir_all(first.join("nested")).unwrap();
+    fs::create_dir_all(&second).unwrap();
+    fs::write(first.join("a.txt"), b"aaaa").unwrap();
+    fs::write(first.join("nested").join("b.txt"), b"bb").unwrap();
+    fs::write(second.join("c.txt"), b"c").unwrap();
+    fs::write(second.join("skip.log"), b"log").unwrap();
+
+    let total = fastdel::DeletionStats::default();
+    let stats = delete(&first, DeleteOptions::default()).await.unwrap();
+    total.merge_from(&stats);
+    let options = DeleteOptions {
+        excludes: vec!["*.log".to_string()],
+        ..DeleteOptions::default()
+    };
+    let stats = delete(&second, options).await.unwrap();
+    total.merge_from(&stats);
+
+    assert_eq!(total.files_deleted(), 3);
+    assert_eq!(total.dirs_deleted(), 2);
+    assert_eq!(total.skipped(), 1);
+    assert_eq!(total.errors_encountered(), 0);
+}
+
+#[tokio::test]
+async fn keep_file_leaves_placeholder_in_emptied_root() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("build");
+    fs::create_dir_all(target.join("out")).unwrap();
+    fs::write(target.join("out").join("app.bin"), b"binary").unwrap();
+    fs::write(target.join(".gitkeep"), b"").unwrap();
+
+    let options = DeleteOptions {
+        keep_root: true,
+        keep_file: Some(".gitkeep".to_string()),
+        ..DeleteOptions::default()
+    };
+    let stats = delete(&target, options).await.unwrap();
+
+    let remaining: Vec<_> = fs::read_dir(&target).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+    assert_eq!(remaining, vec![std::ffi::OsString::from(".gitkeep")]);
+    assert_eq!(stats.placeholders_created(), 1);
+}
+
+#[tokio::test]
+async fn preview_tree_limits_depth_and_width() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    fs::create_dir_all(target.join("big").join("deeper")).unwrap();
+    fs::write(target.join("big").join("deeper").join("huge.bin"), vec![0u8; 64 * 1024]).unwrap();
+    for i in 0..3 {
+        fs::write(target.join(format!("small{}.txt", i)), b"x").unwrap();
+    }
+
+    let preview = fastdel::preview_tree(&target, Some(1), Some(2)).await;
+
+    assert_eq!(preview.totals.files, 4);
+    assert_eq!(preview.children.len(), 2);
+    assert_eq!(preview.hidden, 2);
+    assert_eq!(preview.children[0].path, target.join("big"));
+    assert!(preview.children[0].children.is_empty());
+    assert_eq!(preview.children[0].totals.files, 1);
+}
+
+#[test]
+fn contains_dir_compares_canonical_paths() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    let nested = target.join("nested");
+    let sibling = sandbox.path().join("sibling");
+    fs::create_dir_all(&nested).unwrap();
+    fs::create_dir_all(&sibling).unwrap();
+
+    assert!(fastdel::contains_dir(&target, &target));
+    assert!(fastdel::contains_dir(&target, &nested));
+    assert!(fastdel::contains_dir(&nested.join(".."), &nested));
+    assert!(!fastdel::contains_dir(&nested, &target));
+    assert!(!fastdel::contains_dir(&target, &sibling));
+    assert!(!fastdel::contains_dir(&target.join("missing"), &nested));
+}
+
+#[tokio::test]
+async fn report_empty_lists_only_originally_empty_directories() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    fs::create_dir_all(target.join("scaffold").join("empty")).unwrap();
+    fs::create_dir_all(target.join("blank")).unwrap();
+    fs::create_dir_all(target.join("full")).unwrap();
+    fs::write(target.join("full").join("file.txt"), b"data").unwrap();
+
+    let options = DeleteOptions {
+        report_empty: true,
+        ..DeleteOptions::default()
+    };
+    let stats = delete(&target, options).await.unwrap();
+
+    assert!(!target.exists());
+    assert_eq!(
+        stats.empty_dirs(),
+        vec![target.join("blank"), target.join("scaffold").join("empty")]
+    );
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn deeply_nested_chain_is_deleted() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    // Two bytes per level keeps the deepest path well under PATH_MAX
+    let deepest = (0..1500).fold(target.clone(), |path, _| path.join("d"));
+    fs::create_dir_all(&deepest).unwrap();
+    fs::write(deepest.join("leaf.txt"), b"data").unwrap();
+
+    let stats = delete(&target, DeleteOptions::default()).await.unwrap();
+
+    assert!(!target.exists());
+    assert_eq!(stats.errors_encountered(), 0);
+    assert_eq!(stats.files_deleted(), 1);
+    assert_eq!(stats.dirs_deleted(), 1501);
+}
+
+#[tokio::test]
+async fn exclude_larger_than_keeps_big_files_and_their_parents() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("cache");
+    let models = target.join("models").join("llm");
+    fs::create_dir_all(&models).unwrap();
+    fs::create_dir_all(target.join("tmp")).unwrap();
+    fs::write(models.join("weights.bin"), vec![0u8; 4096]).unwrap();
+    fs::write(models.join("config.json"), b"{}").unwrap();
+    fs::write(target.join("tmp").join("chunk"), vec![0u8; 1024]).unwrap();
+
+    let options = DeleteOptions {
+        exclude_larger_than: Some(1024),
+        ..DeleteOptions::default()
+    };
+    let stats = delete(&target, options).await.unwrap();
+
+    assert!(models.join("weights.bin").exists());
+    assert!(!models.join("config.json").exists());
+    assert!(!target.join("tmp").exists());
+    assert_eq!(stats.skipped(), 1);
+    assert_eq!(stats.errors_encountered(), 0);
+}
+
+#[tokio::test]
+async fn trailing_separator_on_a_file_is_not_a_directory() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let file = sandbox.path().join("file.txt");
+    let dir = sandbox.path().join("dir");
+    fs::write(&file, b"data").unwrap();
+    fs::create_dir(&dir).unwrap();
+
+    let with_slash = PathBuf::from(format!("{}/", file.display()));
+    let error = fastdel::refuse_file_as_directory(&with_slash).await.unwrap_err();
+    assert!(error.to_string().contains("Not a directory"));
+
+    assert!(fastdel::refuse_file_as_directory(&file).await.is_ok());
+    assert!(fastdel::refuse_file_as_directory(&PathBuf::from(format!("{}/", dir.display()))).await.is_ok());
+    assert!(fastdel::refuse_file_as_directory(&sandbox.path().join("missing/")).await.is_ok());
+    assert!(file.exists());
+}
+
+#[cfg(windows)]
+#[tokio::test]
+async fn trailing_backslash_on_a_file_is_not_a_directory() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let file = sandbox.path().join("file.txt");
+    fs::write(&file, b"data").unwrap();
+
+    let with_backslash = PathBuf::from(format!("{}\\", file.display()));
+    assert!(fastdel::refuse_file_as_directory(&with_backslash).await.is_err());
+    assert!(fastdel::refuse_file_as_directory(&PathBuf::from(format!("{}\\\\", file.display()))).await.is_err());
+}
+
+#[test]
+fn invalid_progress_template_is_rejected_up_front() {
+    let options = DeleteOptions {
+        progress_template: Some("}{".to_string()),
+        ..DeleteOptions::default()
+    };
+    let error = fastdel::DeletionEngine::new(options).err().unwrap();
+    assert!(error.to_string().contains("--progress-template"));
+
+    let options = DeleteOptions {
+        progress_template: Some("{spinner} {pos}/{len} {msg}".to_string()),
+        ..DeleteOptions::default()
+    };
+    assert!(fastdel::DeletionEngine::new(options).is_ok());
+}
+
+#[tokio::test]
+async fn breadth_first_order_deletes_the_whole_tree() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    for branch in ["a", "b", "c"] {
+        let deep = target.join(branch).join("x").join("y");
+        fs::create_dir_all(&deep).unwrap();
+        fs::write(target.join(branch).join("top.txt"), b"data").unwrap();
+        fs::write(deep.join("leaf.txt"), b"data").unwrap();
+    }
+
+    let options = DeleteOptions {
+        delete_order: fastdel::DeleteOrder::Bfs,
+        ..DeleteOptions::default()
+    };
+    let stats = delete(&target, options).await.unwrap();
+
+    assert!(!target.exists());
+    assert_eq!(stats.files_deleted(), 6);
+    assert_eq!(stats.dirs_deleted(), 10);
+    assert_eq!(stats.errors_encountered(), 0);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn skip_special_keeps_fifos_and_sockets() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    let run = target.join("run");
+    fs::create_dir_all(&run).unwrap();
+    fs::write(target.join("file.txt"), b"data").unwrap();
+    let status = std::process::Command::new("mkfifo").arg(run.join("pipe")).status().unwrap();
+    assert!(status.success());
+    let _socket = std::os::unix::net::UnixListener::bind(run.join("socket")).unwrap();
+
+    let options = DeleteOptions {
+        skip_special: true,
+        ..DeleteOptions::default()
+    };
+    let stats = delete(&target, options).await.unwrap();
+
+    assert!(!target.join("file.txt").exists());
+    assert!(run.join("pipe").exists());
+    assert!(run.join("socket").exists());
+    assert_eq!(stats.special_kept(), vec![run.join("pipe"), run.join("socket")]);
+    assert_eq!(stats.errors_encountered(), 0);
+}
+
+#[tokio::test]
+async fn newer_than_only_deletes_recent_files() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    fs::create_dir_all(&target).unwrap();
+    fs::write(target.join("fresh.o"), b"data").unwrap();
+    let stale = fs::File::create(target.join("stale.o")).unwrap();
+    let ten_days = std::time::Duration::from_secs(10 * 24 * 60 * 60);
+    stale.set_modified(std::time::SystemTime::now() - ten_days).unwrap();
+
+    let options = DeleteOptions {
+        newer_than: Some(std::time::Duration::from_secs(24 * 60 * 60)),
+        ..DeleteOptions::default()
+    };
+    let stats = delete(&target, options).await.unwrap();
+
+    assert!(!target.join("fresh.o").exists());
+    assert!(target.join("stale.o").exists());
+    assert_eq!(stats.files_deleted(), 1);
+
+    let inverted = DeleteOptions {
+        newer_than: Some(std::time::Duration::from_secs(60)),
+        older_than: Some(std::time::Duration::from_secs(3600)),
+        ..DeleteOptions::default()
+    };
+    assert!(fastdel::DeletionEngine::new(inverted).is_err());
+}
+
+#[tokio::test]
+async fn path_filter_matches_full_paths_and_inverts() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    for build in ["build-1", "build-22", "build-x"] {
+        fs::create_dir_all(target.join(build)).unwrap();
+        fs::write(target.join(build).join("main.o"), b"data").unwrap();
+    }
+
+    let options = DeleteOptions {
+        path_filter: Some(r"/build-[0-9]+/.*\.o$".to_string()),
+        ..DeleteOptions::default()
+    };
+    let stats = delete(&target, options).await.unwrap();
+
+    assert!(!target.join("build-1").exists());
+    assert!(!target.join("build-22").exists());
+    assert!(target.join("build-x").join("main.o").exists());
+    assert_eq!(stats.files_deleted(), 2);
+
+    let options = DeleteOptions {
+        path_filter: Some("(?i)BUILD-X".to_string()),
+        path_filter_invert: true,
+        ..DeleteOptions::default()
+    };
+    let stats = delete(&target, options).await.unwrap();
+
+    assert!(target.join("build-x").join("main.o").exists());
+    assert_eq!(stats.files_deleted(), 0);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn followed_symlink_root_is_emptied_and_kept() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let real = sandbox.path().join("real");
+    let link = sandbox.path().join("link");
+    fs::create_dir_all(real.join("nested")).unwrap();
+    fs::write(real.join("nested").join("file.txt"), b"data").unwrap();
+    std::os::unix::fs::symlink(&real, &link).unwrap();
+
+    let options = DeleteOptions {
+        on_symlink: fastdel::SymlinkPolicy::Follow,
+        ..DeleteOptions::default()
+    };
+    let stats = delete(&link, options).await.unwrap();
+
+    assert!(real.exists());
+    assert_eq!(fs::read_dir(&real).unwrap().count(), 0);
+    assert!(fs::symlink_metadata(&link).is_ok());
+    assert_eq!(stats.kept_roots(), vec![(link.clone(), "a symlink")]);
+    assert_eq!(stats.errors_encountered(), 0);
+}
+
+#[tokio::test]
+async fn progress_callback_reports_every_deleted_entry() {
+    use fastdel::{ProgressCallback, ProgressKind};
+    use std::sync::{Arc, Mutex};
+
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    fs::create_dir_all(target.join("nested")).unwrap();
+    fs::write(target.join("a.txt"), b"12345").unwrap();
+    fs::write(target.join("nested").join("b.txt"), b"123").unwrap();
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let sink = Arc::clone(&events);
+    let options = DeleteOptions {
+        on_progress: Some(ProgressCallback::new(move |event| {
+            sink.lock().unwrap().push((event.path.to_path_buf(), event.kind, event.bytes));
+        })),
+        ..DeleteOptions::default()
+    };
+    let stats = delete(&target, options).await.unwrap();
+
+    let mut events = events.lock().unwrap().clone();
+    events.sort_by(|a, b| a.0.cmp(&b.0));
+    let files: Vec<_> = events.iter().filter(|event| event.1 == ProgressKind::File).collect();
+    let dirs = events.iter().filter(|event| event.1 == ProgressKind::Directory).count();
+    assert_eq!(files.len() as u64, stats.files_deleted());
+    assert_eq!(dirs as u64, stats.dirs_deleted());
+    assert_eq!(files.iter().map(|event| event.2).sum::<u64>(), stats.bytes_freed());
+    assert!(events.iter().all(|event| event.1 != ProgressKind::Error));
+    assert_eq!(events.last().unwrap().0, target.join("nested").join("b.txt"));
+}
+
+#[tokio::test]
+async fn checksum_manifest_records_blake3_of_each_file() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    fs::create_dir_all(target.join("nested")).unwrap();
+    fs::write(target.join("a.txt"), b"hello").unwrap();
+    fs::write(target.join("nested").join("b.txt"), b"").unwrap();
+    let manifest = sandbox.path().join("manifest.jsonl");
+
+    let listed = fastdel::write_checksum_manifest(std::slice::from_ref(&target), &manifest, 4).await.unwrap();
+
+    assert_eq!(listed, 2);
+    let text = fs::read_to_string(&manifest).unwrap();
+    let hashes: Vec<(String, String)> = text
+        .lines()
+        .map(|line| {
+            let entry: serde_json::Value = serde_json::from_str(line).unwrap();
+            (entry["path"].as_str().unwrap().to_string(), entry["blake3"].as_str().unwrap().to_string())
+        })
+        .collect();
+    assert!(hashes.contains(&(
+        target.join("a.txt").to_string_lossy().into_owned(),
+        "ea8f163db38682925e4491c5e58d4bb3506ef8c14eb78a86e908c5624a67200f".to_string()
+    )));
+    assert!(hashes.contains(&(
+        target.join("nested").join("b.txt").to_string_lossy().into_owned(),
+        "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262".to_string()
+    )));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn non_utf8_names_are_deleted_and_reported_unmangled() {
+    use fastdel::{escape_path, ProgressCallback};
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    use std::sync::{Arc, Mutex};
+
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    let odd_dir = target.join(OsStr::from_bytes(b"dir\xFE"));
+    fs::create_dir_all(&odd_dir).unwrap();
+    let odd_file = odd_dir.join(OsStr::from_bytes(b"bad\xFFname.txt"));
+    fs::write(&odd_file, b"data").unwrap();
+
+    let reported = Arc::new(Mutex::new(Vec::new()));
+    let sink = Arc::clone(&reported);
+    let options = DeleteOptions {
+        on_progress: Some(ProgressCallback::new(move |event| {
+            sink.lock().unwrap().push(event.path.to_path_buf());
+        })),
+        ..DeleteOptions::default()
+    };
+    let stats = delete(&target, options).await.unwrap();
+
+    assert!(!target.exists());
+    assert_eq!(stats.errors_encountered(), 0);
+    assert_eq!(stats.files_deleted(), 1);
+    assert!(reported.lock().unwrap().contains(&odd_file));
+    assert_eq!(
+        escape_path(&odd_file).to_string(),
+        format!("{}/dir\\xFE/bad\\xFFname.txt", target.display())
+    );
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn resume_deletes_only_listed_files_that_survived() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    let nested = target.join("nested");
+    fs::create_dir_all(&nested).unwrap();
+    fs::write(target.join("a.txt"), b"gone").unwrap();
+    fs::write(nested.join("b.txt"), b"left").unwrap();
+    let odd = nested.join(OsStr::from_bytes(b"odd\xFF.txt"));
+    fs::write(&odd, b"left").unwrap();
+    let manifest = sandbox.path().join("manifest.jsonl");
+    fastdel::write_manifest(std::slice::from_ref(&target), &manifest).await.unwrap();
+    // The interrupted run got as far as the first file
+    fs::remove_file(target.join("a.txt")).unwrap();
+    fs::write(nested.join("unlisted.txt"), b"new").unwrap();
+
+    let engine = fastdel::DeletionEngine::new(DeleteOptions::default()).unwrap();
+    let already_gone = engine.resume_from_manifest(&manifest).await.unwrap();
+
+    let stats = engine.get_stats();
+    assert_eq!(already_gone, 1);
+    assert_eq!(stats.files_deleted(), 2);
+    assert_eq!(stats.errors_encountered(), 0);
+    assert!(!odd.exists());
+    let left: Vec<PathBuf> = fs::read_dir(&nested).unwrap().map(|entry| entry.unwrap().path()).collect();
+    assert_eq!(left, vec![nested.join("unlisted.txt")]);
+}
+
+#[tokio::test]
+async fn small_trees_are_removed_in_one_call_with_full_stats() {
+    let sandbox = tempfile::tempdir().unwrap();
+    for (name, limit) in [("small", 100), ("over_limit", 4)] {
+        let target = sandbox.path().join(name);
+        fs::create_dir_all(target.join("nested")).unwrap();
+        fs::write(target.join("a.txt"), b"a").unwrap();
+        fs::write(target.join("b.txt"), b"b").unwrap();
+        fs::write(target.join("nested").join("c.txt"), b"c").unwrap();
+
+        let options = DeleteOptions {
+            simple_max_entries: limit,
+            ..DeleteOptions::default()
+        };
+        let stats = delete(&target, options).await.unwrap();
+
+        assert!(!target.exists(), "{} was left behind", name);
+        assert_eq!(stats.files_deleted(), 3);
+        assert_eq!(stats.dirs_deleted(), 2);
+        assert_eq!(stats.errors_encountered(), 0);
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn broken_symlink_root_is_unlinked_when_requested() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let link = sandbox.path().join("broken");
+    std::os::unix::fs::symlink(sandbox.path().join("missing"), &link).unwrap();
+
+    assert!(delete(&link, DeleteOptions::default()).await.is_err());
+
+    let options = DeleteOptions {
+        unlink_root_symlink: true,
+        ..DeleteOptions::default()
+    };
+    let stats = delete(&link, options).await.unwrap();
+
+    assert!(fs::symlink_metadata(&link).is_err());
+    assert_eq!(stats.files_deleted(), 1);
+    assert_eq!(stats.errors_encountered(), 0);
+}
+
+#[tokio::test]
+async fn many_jobs_over_sibling_directories_finish() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    for dir in 0..5 {
+        let dir = target.join(dir.to_string());
+        fs::create_dir_all(&dir).unwrap();
+        for file in 0..200 {
+            fs::write(dir.join(file.to_string()), b"").unwrap();
+        }
+    }
+
+    let options = DeleteOptions {
+        jobs: 8,
+        ..DeleteOptions::default()
+    };
+    let stats = tokio::time::timeout(std::time::Duration::from_secs(60), delete(&target, options))
+        .await
+        .expect("deletion stalled")
+        .unwrap();
+
+    assert!(!target.exists());
+    assert_eq!(stats.files_deleted(), 1000);
+    assert_eq!(stats.dirs_deleted(), 6);
+}
+
+#[tokio::test]
+async fn stats_report_peak_concurrency_and_filesystem_calls() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    fs::create_dir_all(target.join("nested")).unwrap();
+    for file in 0..20 {
+        fs::write(target.join(file.to_string()), b"x").unwrap();
+    }
+
+    let options = DeleteOptions {
+        jobs: 4,
+        ..DeleteOptions::default()
+    };
+    let stats = delete(&target, options).await.unwrap();
+
+    assert!((1..=4).contains(&stats.peak_concurrency()));
+    // One removal and one lookup per entry, plus a listing per directory
+    assert!(stats.syscalls() >= 2 * (stats.files_deleted() + stats.dirs_deleted()));
+}
+
+#[tokio::test]
+async fn assume_empty_fast_delete_skips_metadata_and_sizes() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    fs::create_dir_all(target.join("nested")).unwrap();
+    for file in 0..20 {
+        fs::write(target.join("nested").join(file.to_string()), b"data").unwrap();
+    }
+
+    let filtered = DeleteOptions {
+        assume_empty_fast_delete: true,
+        larger_than: Some(1),
+        ..DeleteOptions::default()
+    };
+    assert!(delete(&target, filtered).await.is_err());
+    assert!(target.exists());
+
+    let options = DeleteOptions {
+        assume_empty_fast_delete: true,
+        ..DeleteOptions::default()
+    };
+    let stats = delete(&target, options).await.unwrap();
+
+    assert!(!target.exists());
+    assert_eq!(stats.files_deleted(), 20);
+    assert_eq!(stats.dirs_deleted(), 2);
+    assert_eq!(stats.bytes_freed(), 0);
+    // Only removals and listings; no lookup per entry
+    assert!(stats.syscalls() < 2 * (stats.files_deleted() + stats.dirs_deleted()));
+}
+
+#[tokio::test]
+async fn file_replaced_by_directory_is_deleted_as_one() {
+    use fastdel::ProgressCallback;
+
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    fs::create_dir_all(&target).unwrap();
+    for name in ["a", "b"] {
+        fs::write(target.join(name), b"x").unwrap();
+    }
+
+    // Once one file is gone, swap the other for a directory if it's still there
+    let swap_target = target.clone();
+    let options = DeleteOptions {
+        jobs: 1,
+        on_progress: Some(ProgressCallback::new(move |event| {
+            let Some(name) = event.path.file_name() else { return };
+            let other = swap_target.join(if name == "a" { "b" } else { "a" });
+            if event.path.parent() == Some(swap_target.as_path()) && other.is_file() {
+                fs::remove_file(&other).unwrap();
+                fs::create_dir(&other).unwrap();
+                fs::write(other.join("inner"), b"x").unwrap();
+            }
+        })),
+        ..DeleteOptions::default()
+    };
+    let stats = delete(&target, options).await.unwrap();
+
+    assert!(!target.exists());
+    assert_eq!(stats.errors_encountered(), 0);
+    assert_eq!(stats.files_deleted(), 2);
+    assert_eq!(stats.dirs_deleted(), 2);
+}
+
+#[tokio::test]
+async fn directory_replaced_by_file_is_deleted_as_one() {
+    use fastdel::ProgressCallback;
+
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    let nested = target.join("nested");
+    fs::create_dir_all(&nested).unwrap();
+    fs::write(nested.join("inner"), b"x").unwrap();
+
+    // Emptied but not yet removed: turn it into a file
+    let swap_dir = nested.clone();
+    let options = DeleteOptions {
+        on_progress: Some(ProgressCallback::new(move |event| {
+            if event.path.parent() == Some(swap_dir.as_path()) {
+                fs::remove_dir(&swap_dir).unwrap();
+                fs::write(&swap_dir, b"x").unwrap();
+            }
+        })),
+        ..DeleteOptions::default()
+    };
+    let stats = delete(&target, options).await.unwrap();
+
+    assert!(!target.exists());
+    assert_eq!(stats.errors_encountered(), 0);
+    assert_eq!(stats.files_deleted(), 2);
+    assert_eq!(stats.dirs_deleted(), 1);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn dereference_root_follows_only_the_target_link() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let real = sandbox.path().join("real");
+    let outside = sandbox.path().join("outside");
+    let link = sandbox.path().join("link");
+    fs::create_dir_all(&real).unwrap();
+    fs::create_dir_all(&outside).unwrap();
+    fs::write(real.join("a.txt"), b"x").unwrap();
+    fs::write(outside.join("keep.txt"), b"keep").unwrap();
+    std::os::unix::fs::symlink(&outside, real.join("inner")).unwrap();
+    std::os::unix::fs::symlink(&real, &link).unwrap();
+
+    let options = DeleteOptions {
+        dereference_root: true,
+        ..DeleteOptions::default()
+    };
+    let stats = delete(&link, options).await.unwrap();
+
+    assert!(!real.exists());
+    assert!(fs::symlink_metadata(&link).is_ok());
+    assert!(outside.join("keep.txt").exists());
+    assert_eq!(stats.files_deleted(), 2);
+    assert_eq!(stats.dirs_deleted(), 1);
+}
+
+#[tokio::test]
+async fn blocking_backend_matches_async_engine() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let build = |name: &str| {
+        let target = sandbox.path().join(name);
+        for dir in 0..4 {
+            let dir = target.join(dir.to_string()).join("nested");
+            fs::create_dir_all(&dir).unwrap();
+            for file in 0..300 {
+                fs::write(dir.join(format!("{}.tmp", file)), b"data").unwrap();
+            }
+            fs::write(dir.join("keep.log"), b"keep").unwrap();
+        }
+        target
+    };
+    let options = |blocking| DeleteOptions {
+        jobs: 4,
+        excludes: vec!["*.log".to_string()],
+        blocking,
+        ..DeleteOptions::default()
+    };
+
+    let async_target = build("async");
+    let expected = delete(&async_target, options(false)).await.unwrap();
+    let blocking_target = build("blocking");
+    let stats = delete(&blocking_target, options(true)).await.unwrap();
+
+    assert_eq!(stats.files_deleted(), 1200);
+    assert_eq!(stats.files_deleted(), expected.files_deleted());
+    assert_eq!(stats.dirs_deleted(), expected.dirs_deleted());
+    assert_eq!(stats.bytes_freed(), expected.bytes_freed());
+    assert_eq!(stats.errors_encountered(), 0);
+    assert!(blocking_target.join("3").join("nested").join("keep.log").exists());
+    assert!(!blocking_target.join("3").join("nested").join("0.tmp").exists());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 3)]
+async fn blocking_backend_shares_its_jobs_across_roots() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let targets: Vec<PathBuf> = (0..3)
+        .map(|root| {
+            let target = sandbox.path().join(root.to_string());
+            for dir in 0..3 {
+                let dir = target.join(dir.to_string());
+                fs::create_dir_all(&dir).unwrap();
+                for file in 0..200 {
+                    fs::write(dir.join(format!("{}.tmp", file)), b"data").unwrap();
+                }
+            }
+            target
+        })
+        .collect();
+    let options = DeleteOptions {
+        jobs: 2,
+        blocking: true,
+        ..DeleteOptions::default()
+    };
+    let engine = std::sync::Arc::new(fastdel::DeletionEngine::new(options).unwrap());
+
+    let runs: Vec<_> = targets
+        .iter()
+        .map(|target| {
+            let (engine, target) = (engine.clone(), target.clone());
+            tokio::spawn(async move { engine.delete_directory(&target).await })
+        })
+        .collect();
+    for run in runs {
+        run.await.unwrap().unwrap();
+    }
+
+    let stats = engine.get_stats();
+    assert!(targets.iter().all(|target| !target.exists()));
+    assert_eq!(stats.files_deleted(), 1800);
+    assert_eq!(stats.dirs_deleted(), 12);
+    assert_eq!(stats.errors_encountered(), 0);
+    assert!(stats.peak_concurrency() <= 2, "{} removals at once", stats.peak_concurrency());
+}
+
+#[tokio::test]
+async fn dry_run_reports_the_real_totals_and_leaves_the_tree_intact() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let build = |name: &str| {
+        let target = sandbox.path().join(name);
+        fs::create_dir_all(target.join("nested")).unwrap();
+        fs::write(target.join("a.txt"), b"aaaa").unwrap();
+        fs::write(target.join("nested").join("b.txt"), b"bb").unwrap();
+        target
+    };
+
+    let dry_target = build("dry");
+    let options = DeleteOptions {
+        dry_run: true,
+        ..DeleteOptions::default()
+    };
+    let stats = delete(&dry_target, options).await.unwrap();
+    let real_target = build("real");
+    let expected = delete(&real_target, DeleteOptions::default()).await.unwrap();
+
+    assert!(dry_target.join("a.txt").exists());
+    assert!(dry_target.join("nested").join("b.txt").exists());
+    assert!(!real_target.exists());
+    assert_eq!(stats.files_deleted(), 2);
+    assert_eq!(stats.dirs_deleted(), 2);
+    assert_eq!(stats.bytes_freed(), expected.bytes_freed());
+    assert_eq!(stats.errors_encountered(), 0);
+}
+
+#[tokio::test]
+async fn exclude_keeps_matches_by_name_or_relative_path_and_their_parents() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    fs::create_dir_all(target.join(".git").join("objects")).unwrap();
+    fs::create_dir_all(target.join("src").join("logs")).unwrap();
+    fs::create_dir_all(target.join("build")).unwrap();
+    fs::write(target.join(".git").join("objects").join("pack"), b"git").unwrap();
+    fs::write(target.join("src").join("main.rs"), b"code").unwrap();
+    fs::write(target.join("src").join("logs").join("run.log"), b"log").unwrap();
+    fs::write(target.join("build").join("out.o"), b"obj").unwrap();
+
+    let options = DeleteOptions {
+        excludes: vec![".git".to_string(), "src/**/*.log".to_string()],
+        ..DeleteOptions::default()
+    };
+    let stats = delete(&target, options).await.unwrap();
+
+    assert!(target.join(".git").join("objects").join("pack").exists());
+    assert!(target.join("src").join("logs").join("run.log").exists());
+    assert!(!target.join("src").join("main.rs").exists());
+    assert!(!target.join("build").exists());
+    assert_eq!(stats.files_deleted(), 2);
+    assert_eq!(stats.dirs_deleted(), 1);
+    assert_eq!(stats.skipped(), 2);
+    assert_eq!(stats.errors_encountered(), 0);
+}
+
+#[tokio::test]
+async fn max_depth_keeps_directories_below_the_limit() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    fs::create_dir_all(target.join("one").join("two")).unwrap();
+    fs::create_dir_all(target.join("flat")).unwrap();
+    fs::write(target.join("root.txt"), b"0").unwrap();
+    fs::write(target.join("one").join("one.txt"), b"1").unwrap();
+    fs::write(target.join("one").join("two").join("two.txt"), b"2").unwrap();
+    fs::write(target.join("flat").join("flat.txt"), b"1").unwrap();
+
+    let options = DeleteOptions {
+        max_depth: Some(1),
+        ..DeleteOptions::default()
+    };
+    let stats = delete(&target, options).await.unwrap();
+
+    assert!(!target.join("root.txt").exists());
+    assert!(!target.join("one").join("one.txt").exists());
+    assert!(!target.join("flat").exists());
+    assert!(target.join("one").join("two").join("two.txt").exists());
+    assert_eq!(stats.files_deleted(), 3);
+    assert_eq!(stats.dirs_deleted(), 1);
+    assert_eq!(stats.skipped(), 1);
+    assert_eq!(stats.errors_encountered(), 0);
+}
+
+#[tokio::test]
+async fn keep_root_empties_the_directory_but_keeps_it() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("cache");
+    fs::create_dir_all(target.join("nested")).unwrap();
+    fs::write(target.join("a.bin"), b"a").unwrap();
+    fs::write(target.join("nested").join("b.bin"), b"b").unwrap();
+
+    let options = DeleteOptions {
+        keep_root: true,
+        ..DeleteOptions::default()
+    };
+    let stats = delete(&target, options).await.unwrap();
+
+    assert!(target.is_dir());
+    assert_eq!(fs::read_dir(&target).unwrap().count(), 0);
+    assert_eq!(stats.files_deleted(), 2);
+    assert_eq!(stats.dirs_deleted(), 1);
+    assert_eq!(stats.placeholders_created(), 0);
+    assert_eq!(stats.errors_encountered(), 0);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn one_file_system_skips_directories_on_another_device() {
+    use std::os::unix::fs::MetadataExt;
+
+    let sandbox = tempfile::tempdir().unwrap();
+    // A followed link reaches another filesystem without mounting one
+    let Ok(elsewhere) = tempfile::tempdir_in("/dev/shm") else {
+        return;
+    };
+    if fs::metadata(elsewhere.path()).unwrap().dev() == fs::metadata(sandbox.path()).unwrap().dev() {
+        return;
+    }
+    let target = sandbox.path().join("target");
+    fs::create_dir_all(target.join("local")).unwrap();
+    fs::write(target.join("local").join("a.txt"), b"a").unwrap();
+    fs::write(elsewhere.path().join("b.txt"), b"b").unwrap();
+    std::os::unix::fs::symlink(elsewhere.path(), target.join("mounted")).unwrap();
+
+    let options = DeleteOptions {
+        on_symlink: fastdel::SymlinkPolicy::Follow,
+        one_file_system: true,
+        ..DeleteOptions::default()
+    };
+    let stats = delete(&target, options).await.unwrap();
+
+    assert!(elsewhere.path().join("b.txt").exists());
+    assert!(target.join("mounted").exists());
+    assert!(!target.join("local").exists());
+    assert_eq!(stats.mounts_skipped(), 1);
+    assert_eq!(stats.files_deleted(), 1);
+    assert_eq!(stats.errors_encountered(), 0);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn retries_outlast_a_failure_that_clears() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    fs::create_dir_all(target.join("locked")).unwrap();
+    let stuck = target.join("locked").join("stuck.txt");
+    fs::write(&stuck, b"data").unwrap();
+    if !common::pin(&stuck) {
+        common::unpin(&stuck);
+        return;
+    }
+    // Released while the engine backs off: 10ms, 20ms, 40ms, ... between attempts
+    let release = {
+        let stuck = stuck.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            common::unpin(&stuck);
+        })
+    };
+
+    let options = DeleteOptions {
+        retries: 8,
+        ..DeleteOptions::default()
+    };
+    let stats = delete(&target, options).await.unwrap();
+    release.join().unwrap();
+
+    assert!(!target.exists());
+    assert_eq!(stats.files_deleted(), 1);
+    assert_eq!(stats.errors_encountered(), 0);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn exhausted_retries_count_one_error() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    fs::create_dir_all(target.join("locked")).unwrap();
+    let stuck = target.join("locked").join("stuck.txt");
+    fs::write(&stuck, b"data").unwrap();
+    if !common::pin(&stuck) {
+        common::unpin(&stuck);
+        return;
+    }
+
+    let options = DeleteOptions {
+        retries: 2,
+        ..DeleteOptions::default()
+    };
+    let result = delete(&target, options).await;
+    common::unpin(&stuck);
+
+    let stats = result.unwrap();
+    assert!(stuck.exists());
+    assert_eq!(stats.errors_encountered(), 1);
+    assert_eq!(stats.errors_in(fastdel::ErrorCategory::PermissionDenied), 1);
+}
+
+#[tokio::test]
+async fn older_than_only_deletes_stale_files_and_keeps_their_parents() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    fs::create_dir_all(target.join("mixed")).unwrap();
+    fs::create_dir_all(target.join("all_stale")).unwrap();
+    let ten_days = std::time::Duration::from_secs(10 * 24 * 60 * 60);
+    for stale in [target.join("mixed").join("stale.log"), target.join("all_stale").join("old.log")] {
+        let file = fs::File::create(stale).unwrap();
+        file.set_modified(std::time::SystemTime::now() - ten_days).unwrap();
+    }
+    fs::write(target.join("mixed").join("fresh.log"), b"data").unwrap();
+
+    let options = DeleteOptions {
+        older_than: Some(std::time::Duration::from_secs(24 * 60 * 60)),
+        ..DeleteOptions::default()
+    };
+    let stats = delete(&target, options).await.unwrap();
+
+    assert!(!target.join("mixed").join("stale.log").exists());
+    assert!(target.join("mixed").join("fresh.log").exists());
+    assert!(!target.join("all_stale").exists());
+    assert_eq!(stats.files_deleted(), 2);
+    assert_eq!(stats.dirs_deleted(), 1);
+    assert_eq!(stats.errors_encountered(), 0);
+}
+
+#[tokio::test]
+async fn size_filters_select_a_window_of_file_sizes() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    fs::create_dir_all(&target).unwrap();
+    fs::write(target.join("small.bin"), vec![0u8; 10]).unwrap();
+    fs::write(target.join("medium.bin"), vec![0u8; 1_000]).unwrap();
+    fs::write(target.join("large.bin"), vec![0u8; 100_000]).unwrap();
+
+    let options = DeleteOptions {
+        larger_than: Some(100),
+        smaller_than: Some(10_000),
+        ..DeleteOptions::default()
+    };
+    let stats = delete(&target, options).await.unwrap();
+
+    assert!(target.join("small.bin").exists());
+    assert!(!target.join("medium.bin").exists());
+    assert!(target.join("large.bin").exists());
+    assert_eq!(stats.files_deleted(), 1);
+    assert_eq!(stats.dirs_deleted(), 0);
+    assert_eq!(stats.errors_encountered(), 0);
+}
+
+#[tokio::test]
+async fn gitignore_deletes_only_ignored_entries_with_nested_rules() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("repo");
+    fs::create_dir_all(target.join("target").join("debug")).unwrap();
+    fs::create_dir_all(target.join("src")).unwrap();
+    fs::write(target.join(".gitignore"), "/target\n*.log\n").unwrap();
+    fs::write(target.join("src").join(".gitignore"), "!keep.log\n").unwrap();
+    fs::write(target.join("target").join("debug").join("app"), b"bin").unwrap();
+    fs::write(target.join("build.log"), b"log").unwrap();
+    fs::write(target.join("src").join("main.rs"), b"code").unwrap();
+    fs::write(target.join("src").join("keep.log"), b"kept").unwrap();
+
+    let options = DeleteOptions {
+        gitignore: true,
+        ..DeleteOptions::default()
+    };
+    let stats = delete(&target, options).await.unwrap();
+
+    assert!(!target.join("target").exists());
+    assert!(!target.join("build.log").exists());
+    assert!(target.join(".gitignore").exists());
+    assert!(target.join("src").join("main.rs").exists());
+    assert!(target.join("src").join("keep.log").exists());
+    assert_eq!(stats.files_deleted(), 2);
+    assert_eq!(stats.errors_encountered(), 0);
+}
+
+#[tokio::test]
+async fn log_file_records_every_deletion_and_appends_across_runs() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let log = sandbox.path().join("fastdel.log");
+    for name in ["first", "second"] {
+        let target = sandbox.path().join(name);
+        fs::create_dir_all(&target).unwrap();
+        fs::write(target.join("a.txt"), b"a").unwrap();
+        let options = DeleteOptions {
+            log_file: Some(log.clone()),
+            ..DeleteOptions::default()
+        };
+        delete(&target, options).await.unwrap();
+    }
+
+    let text = fs::read_to_string(&log).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 4, "{}", text);
+    for (line, name) in lines.iter().zip(["first", "first", "second", "second"]) {
+        assert!(line.contains(" INFO  Deleted "), "{}", line);
+        assert!(line.contains(name), "{}", line);
+    }
+    assert!(lines[0].ends_with(&format!("Deleted file: {}", sandbox.path().join("first").join("a.txt").display())));
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn failed_log_flush_keeps_the_deletion_error() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let options = DeleteOptions {
+        // Every write to /dev/full fails once it reaches the device
+        log_file: Some(PathBuf::from("/dev/full")),
+        ..DeleteOptions::default()
+    };
+    let error = delete(sandbox.path().join("missing"), options).await.unwrap_err();
+
+    let message = format!("{:#}", error);
+    assert!(message.contains("Failed to access path"), "{}", message);
+    assert!(message.contains("Failed to flush log file"), "{}", message);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn hardlinked_bytes_are_counted_once_and_only_when_freed() {
+    use std::os::unix::fs::MetadataExt;
+
+    let sandbox = tempfile::tempdir().unwrap();
+    // The traversal and the single remove_dir_all for small trees
+    for (name, simple_max_entries) in [("traversed", 0), ("small", 100)] {
+        let target = sandbox.path().join(name);
+        fs::create_dir_all(target.join("nested")).unwrap();
+        fs::write(target.join("data.bin"), vec![1u8; 16 * 1024]).unwrap();
+        fs::hard_link(target.join("data.bin"), target.join("copy.bin")).unwrap();
+        fs::hard_link(target.join("data.bin"), target.join("nested").join("copy.bin")).unwrap();
+        // Still linked from outside the target, so deleting it frees nothing
+        fs::write(target.join("shared.bin"), vec![2u8; 16 * 1024]).unwrap();
+        let outside = sandbox.path().join(format!("{}.bin", name));
+        fs::hard_link(target.join("shared.bin"), &outside).unwrap();
+        let usage = fs::metadata(target.join("data.bin")).unwrap().blocks() * 512;
+
+        let options = DeleteOptions {
+            simple_max_entries,
+            ..DeleteOptions::default()
+        };
+        let stats = delete(&target, options).await.unwrap();
+
+        assert!(!target.exists());
+        assert!(outside.exists());
+        assert_eq!(stats.files_deleted(), 4, "{}", name);
+        assert_eq!(stats.bytes_freed(), usage, "{}", name);
+        assert_eq!(stats.errors_encountered(), 0);
+    }
+}
+
+#[tokio::test]
+async fn verify_reports_entries_that_reappear_after_removal() {
+    use fastdel::{ProgressCallback, ProgressKind};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    fs::create_dir_all(&target).unwrap();
+    let flaky = target.join("flaky.txt");
+    fs::write(&flaky, b"data").unwrap();
+    fs::write(target.join("steady.txt"), b"data").unwrap();
+
+    // Progress is reported before the check, so recreating the file here
+    // stands in for a filesystem that silently drops the unlink
+    let recreated = AtomicBool::new(false);
+    let watched = flaky.clone();
+    let options = DeleteOptions {
+        verify: true,
+        on_progress: Some(ProgressCallback::new(move |event| {
+            if event.kind == ProgressKind::File && event.path == watched && !recreated.swap(true, Ordering::Relaxed) {
+                fs::write(&watched, b"back").unwrap();
+            }
+        })),
+        ..DeleteOptions::default()
+    };
+    let stats = delete(&target, options).await.unwrap();
+
+    assert_eq!(stats.verification_failures(), 1);
+    assert_eq!(stats.survivors(), vec![flaky]);
+    assert_eq!(stats.errors_encountered(), 0);
+    // The directory rescan still removed the recreated file
+    assert!(!target.exists());
+}
+
+#[tokio::test]
+async fn min_free_stops_once_enough_space_is_freed() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    fs::create_dir_all(&target).unwrap();
+    for file in 0..10 {
+        fs::write(target.join(format!("{}.bin", file)), vec![1u8; 64 * 1024]).unwrap();
+    }
+
+    let options = DeleteOptions {
+        jobs: 1,
+        min_free: Some(150_000),
+        ..DeleteOptions::default()
+    };
+    let engine = fastdel::DeletionEngine::new(options).unwrap();
+    engine.delete_directory(&target).await.unwrap();
+
+    let stats = engine.get_stats();
+    assert!(engine.min_free_reached());
+    assert!(stats.bytes_freed() >= 150_000);
+    assert_eq!(stats.files_deleted(), 3);
+    assert_eq!(fs::read_dir(&target).unwrap().count(), 7);
+    assert_eq!(stats.errors_encountered(), 0);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn fail_fast_turns_the_first_failure_into_an_error() {
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    fs::create_dir_all(target.join("locked")).unwrap();
+    let stuck = target.join("locked").join("stuck.txt");
+    fs::write(&stuck, b"data").unwrap();
+    if !common::pin(&stuck) {
+        common::unpin(&stuck);
+        return;
+    }
+
+    let options = DeleteOptions {
+        fail_fast: true,
+        ..DeleteOptions::default()
+    };
+    let result = delete(&target, options).await;
+    common::unpin(&stuck);
+
+    let error = format!("{:#}", result.expect_err("the failure should abort the run"));
+    assert!(error.contains("stuck.txt"), "{}", error);
+    assert!(error.contains("--fail-fast"), "{}", error);
+    assert!(stuck.exists());
+}
+
+#[tokio::test]
+async fn largest_first_deletes_files_in_order_of_size() {
+    use fastdel::{ProgressCallback, ProgressKind};
+    use std::sync::{Arc, Mutex};
+
+    let sandbox = tempfile::tempdir().unwrap();
+    let target = sandbox.path().join("target");
+    // Spread over directories so the traversal order would differ
+    let sizes = [("a/small", 1), ("a/b/largest", 64), ("c/medium", 16), ("large", 32)];
+    for (name, kib) in sizes {
+        let path = target.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, vec![0u8; kib * 1024]).unwrap();
+    }
+
+    let order = Arc::new(Mutex::new(Vec::new()));
+    let seen = order.clone();
+    let options = DeleteOptions {
+        jobs: 1,
+        largest_first: true,
+        on_progress: Some(ProgressCallback::new(move |event| {
+            if event.kind == ProgressKind::File {
+                seen.lock().unwrap().push(event.path.file_name().unwrap().to_owned());
+            }
+        })),
+        ..DeleteOptions::default()
+    };
+    let stats = delete(&target, options).await.unwrap();
+
+    assert!(!target.exists());
+    assert_eq!(stats.files_deleted(), 4);
+    assert_eq!(*order.lock().unwrap(), ["largest", "large", "medium", "small"]);
+}